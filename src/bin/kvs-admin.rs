@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+
+use kvs::{EntryKind, KvStore, Result};
+
+#[derive(StructOpt)]
+enum Config {
+    #[structopt(about = "Check the consistency of a data directory")]
+    Verify {
+        #[structopt(required = true, parse(from_os_str), help = "A data directory")]
+        dir: PathBuf,
+        #[structopt(long, help = "Rewrite corrupt generations keeping readable records")]
+        repair: bool,
+    },
+    #[structopt(about = "Print the decoded records of a data directory")]
+    Dump {
+        #[structopt(required = true, parse(from_os_str), help = "A data directory")]
+        dir: PathBuf,
+        #[structopt(long, help = "Only print records of this key")]
+        key: Option<String>,
+        #[structopt(long, help = "Only print records of this generation")]
+        gen: Option<u64>,
+    },
+}
+
+fn main() -> Result<()> {
+    let config = Config::from_args();
+
+    match config {
+        Config::Verify { dir, repair } => {
+            let report = if repair {
+                KvStore::repair(dir)?
+            } else {
+                KvStore::verify(dir)?
+            };
+
+            for gen in &report.generations {
+                match (gen.stale_ratio(), gen.live_keys) {
+                    (Some(stale_ratio), Some(live_keys)) => println!(
+                        "{}.log: {} records, {} bytes, {:.1}% stale, {} live keys",
+                        gen.gen,
+                        gen.records,
+                        gen.total_bytes,
+                        stale_ratio * 100.0,
+                        live_keys
+                    ),
+                    _ => println!(
+                        "{}.log: {} records ({} encrypted), {} bytes, stale bytes and live keys unavailable",
+                        gen.gen, gen.records, gen.sealed_records, gen.total_bytes
+                    ),
+                }
+                if gen.is_orphaned() {
+                    println!("  orphaned: no live records");
+                }
+                for range in &gen.corrupt {
+                    println!("  corrupt: bytes {}..{}", range.start, range.end);
+                }
+            }
+            match report.live_keys {
+                Some(live_keys) => println!(
+                    "{} generations, {} live keys",
+                    report.generations.len(),
+                    live_keys
+                ),
+                None => println!(
+                    "{} generations, live keys unavailable for encrypted records",
+                    report.generations.len()
+                ),
+            }
+
+            if !report.is_clean() {
+                if repair {
+                    println!("Corrupt generations repaired");
+                } else {
+                    println!("Corruption found, run with --repair to salvage readable records");
+                    exit(1);
+                }
+            }
+        }
+        Config::Dump { dir, key, gen } => {
+            KvStore::dump(dir, gen, key.as_deref(), |entry| {
+                let record = match &entry.kind {
+                    EntryKind::Set { key, value } => format!("set\t{}\t{}", key, value),
+                    EntryKind::SetBlob { key, file, len } => {
+                        format!("blob\t{}\t{}.blob\t{}", key, file, len)
+                    }
+                    EntryKind::Rm { key } => format!("rm\t{}", key),
+                    EntryKind::Sealed { key_id } => format!("sealed\t{}", key_id),
+                    EntryKind::Corrupt => "corrupt".to_owned(),
+                };
+                println!("{}\t{}\t{}\t{}", entry.gen, entry.offset, entry.len, record);
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,8 +1,69 @@
 use std::env::current_dir;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::str::FromStr;
 use structopt::StructOpt;
+use tracing::Level;
 
-use kvs::{KvsEngine, Result};
+use kvs::{KvStore, KvsEngine, LsmStore, MemoryEngine, Result};
+
+/// Snapshot file of the memory engine in the working directory.
+const SNAPSHOT_FILE: &str = "kvs.snapshot";
+/// Name of the engine which first wrote to the working directory.
+const ENGINE_FILE: &str = "kvs.engine";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Engine {
+    Kvs,
+    Lsm,
+    Memory,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "kvs" => Ok(Engine::Kvs),
+            "lsm" => Ok(Engine::Lsm),
+            "memory" => Ok(Engine::Memory),
+            _ => Err(format!("unknown engine: {}", s)),
+        }
+    }
+}
+
+impl Engine {
+    fn name(self) -> &'static str {
+        match self {
+            Engine::Kvs => "kvs",
+            Engine::Lsm => "lsm",
+            Engine::Memory => "memory",
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct Opt {
+    #[structopt(
+        long,
+        global = true,
+        default_value = "kvs",
+        possible_values = &["kvs", "lsm", "memory"],
+        help = "Storage engine used in the working directory"
+    )]
+    engine: Engine,
+    #[structopt(
+        long,
+        global = true,
+        default_value = "warn",
+        help = "Maximum level of log messages written to stderr"
+    )]
+    log_level: Level,
+    #[structopt(subcommand)]
+    config: Config,
+}
 
 #[derive(StructOpt)]
 #[allow(dead_code)]
@@ -24,18 +85,43 @@ enum Config {
         #[structopt(required = true, help = "A string key")]
         key: String,
     },
+    #[structopt(about = "Print storage statistics")]
+    Stats,
+    #[structopt(about = "Write all key/value pairs as JSON Lines")]
+    Export {
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "Output file, stdout if omitted"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(about = "Set all key/value pairs read as JSON Lines")]
+    Import {
+        #[structopt(parse(from_os_str), help = "Input file, stdin if omitted")]
+        input: Option<PathBuf>,
+    },
 }
 
 fn main() -> Result<()> {
-    let config = Config::from_args();
+    let Opt {
+        engine,
+        log_level,
+        config,
+    } = Opt::from_args();
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(log_level)
+        .init();
 
     match config {
         Config::Set { key, value } => {
-            let mut storage = kvs::KvStore::open(current_dir()?)?;
+            let mut storage = open_engine(engine, false)?;
             storage.set(key, value)?;
         }
         Config::Get { key } => {
-            let mut storage = kvs::KvStore::open(current_dir()?)?;
+            let mut storage = open_engine(engine, true)?;
             if let Some(value) = storage.get(key)? {
                 println!("{}", value);
             } else {
@@ -43,7 +129,7 @@ fn main() -> Result<()> {
             }
         }
         Config::Rm { key } => {
-            let mut storage = kvs::KvStore::open(current_dir()?)?;
+            let mut storage = open_engine(engine, false)?;
             match storage.remove(key) {
                 Ok(()) => {}
                 Err(kvs::Error::KeyNotFound(_)) => {
@@ -53,7 +139,93 @@ fn main() -> Result<()> {
                 Err(e) => return Err(e),
             }
         }
+        Config::Stats => {
+            if engine != Engine::Kvs {
+                eprintln!("Statistics are only available for the kvs engine");
+                exit(1);
+            }
+            check_engine(&current_dir()?, engine, true)?;
+            let storage = KvStore::open_read_only(current_dir()?)?;
+            let stats = storage.stats()?;
+            println!("live keys: {}", stats.live_keys);
+            println!("compactions: {}", stats.compactions);
+            if let Some(duration) = stats.last_compaction {
+                println!("last compaction: {:?}", duration);
+            }
+            for gen in &stats.generations {
+                println!(
+                    "{}.log: {} bytes, {} stale",
+                    gen.gen, gen.total_bytes, gen.stale_bytes
+                );
+            }
+        }
+        Config::Export { output } => {
+            let mut storage = open_engine(engine, true)?;
+            match output {
+                Some(path) => kvs::export(&mut storage, BufWriter::new(File::create(path)?))?,
+                None => kvs::export(&mut storage, BufWriter::new(io::stdout().lock()))?,
+            };
+        }
+        Config::Import { input } => {
+            let mut storage = open_engine(engine, false)?;
+            match input {
+                Some(path) => kvs::import(&mut storage, BufReader::new(File::open(path)?))?,
+                None => kvs::import(&mut storage, io::stdin().lock())?,
+            };
+        }
     }
 
     Ok(())
 }
+
+fn open_engine(engine: Engine, read_only: bool) -> Result<Box<dyn KvsEngine>> {
+    let dir = current_dir()?;
+    check_engine(&dir, engine, read_only)?;
+    Ok(match engine {
+        Engine::Kvs if read_only => Box::new(KvStore::open_read_only(dir)?),
+        Engine::Kvs => Box::new(KvStore::open(dir)?),
+        Engine::Lsm => Box::new(LsmStore::open(dir)?),
+        Engine::Memory => Box::new(MemoryEngine::with_snapshot(dir.join(SNAPSHOT_FILE))?),
+    })
+}
+
+/// Exits if `dir` holds data of another engine, and records `engine` as the
+/// engine of a new directory unless `read_only`.
+fn check_engine(dir: &Path, engine: Engine, read_only: bool) -> Result<()> {
+    let recorded = match fs::read_to_string(dir.join(ENGINE_FILE)) {
+        Ok(name) => Some(name.trim().to_owned()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            detect_engine(dir)?.map(|engine| engine.name().to_owned())
+        }
+        Err(err) => return Err(err.into()),
+    };
+    match recorded {
+        Some(name) if name != engine.name() => {
+            eprintln!("The working directory holds data of the {} engine", name);
+            exit(1);
+        }
+        None if !read_only => fs::write(dir.join(ENGINE_FILE), engine.name())?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Guesses the engine of a directory written before engines were recorded.
+fn detect_engine(dir: &Path) -> Result<Option<Engine>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if name == SNAPSHOT_FILE {
+            return Ok(Some(Engine::Memory));
+        } else if name == "MANIFEST" || name == "lsm.wal" || extension == "sst" {
+            return Ok(Some(Engine::Lsm));
+        } else if extension == "log" {
+            return Ok(Some(Engine::Kvs));
+        }
+    }
+    Ok(None)
+}
@@ -1,9 +1,24 @@
 use std::env::current_dir;
+use std::io;
 use std::process::exit;
 use structopt::StructOpt;
+use tracing::{info, Level};
 
 use kvs::{KvsEngine, Result};
 
+#[derive(StructOpt)]
+struct Opt {
+    #[structopt(
+        long,
+        global = true,
+        default_value = "info",
+        help = "Maximum level of log messages written to stderr"
+    )]
+    log_level: Level,
+    #[structopt(subcommand)]
+    config: Config,
+}
+
 #[derive(StructOpt)]
 #[allow(dead_code)]
 enum Config {
@@ -27,7 +42,17 @@ enum Config {
 }
 
 fn main() -> Result<()> {
-    let config = Config::from_args();
+    let Opt { log_level, config } = Opt::from_args();
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(log_level)
+        .init();
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        engine = "kvs",
+        dir = %current_dir()?.display(),
+        "kvs-server starting"
+    );
 
     match config {
         Config::Set { key, value } => {
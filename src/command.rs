@@ -1,7 +1,89 @@
 use serde::{Deserialize, Serialize};
 
+use crate::encryption::{Context, Keyring, Sealed};
+use crate::{Compression, Error, Result};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) enum Command {
-    Set { key: String, value: String },
-    Rm { key: String },
+    Set {
+        key: String,
+        value: String,
+        /// Codec `value` is encoded with, omitted for plain values.
+        #[serde(default, skip_serializing_if = "Compression::is_none")]
+        compression: Compression,
+    },
+    /// A `Set` whose value is kept in a blob file.
+    SetBlob {
+        key: String,
+        blob: BlobRef,
+    },
+    Rm {
+        key: String,
+    },
+    /// Another command, encrypted.
+    Sealed(Sealed),
+}
+
+/// Location and encoding of a value in a `N.blob` file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct BlobRef {
+    pub file: u64,
+    pub pos: u64,
+    pub len: u64,
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+    /// Key the blob is encrypted with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<u32>,
+}
+
+impl Command {
+    pub(crate) fn set(key: String, value: String, compression: Compression) -> Result<Command> {
+        Ok(match compression.encode(&value)? {
+            Some(value) => Command::Set {
+                key,
+                value,
+                compression,
+            },
+            None => Command::Set {
+                key,
+                value,
+                compression: Compression::None,
+            },
+        })
+    }
+
+    /// Encrypts the command, to be written to the log of `gen`, with the current
+    /// key of `keyring`, if it has one.
+    pub(crate) fn seal(self, keyring: &Keyring, gen: u64) -> Result<Command> {
+        let context = Context::Record { gen };
+        Ok(match keyring.seal(&serde_json::to_vec(&self)?, context)? {
+            Some(sealed) => Command::Sealed(sealed),
+            None => self,
+        })
+    }
+
+    /// Decrypts a sealed command read from the log of `gen`, other commands are
+    /// returned as is.
+    pub(crate) fn unseal(self, keyring: &Keyring, gen: u64) -> Result<Command> {
+        match self {
+            Command::Sealed(sealed) => {
+                match serde_json::from_slice(&keyring.open(&sealed, Context::Record { gen })?)? {
+                    Command::Sealed(_) => Err(Error::UnexpectedCommandType),
+                    cmd => Ok(cmd),
+                }
+            }
+            cmd => Ok(cmd),
+        }
+    }
+
+    /// Returns the key the command is about, unknown while it is sealed.
+    pub(crate) fn key(&self) -> Option<&str> {
+        match self {
+            Command::Set { key, .. } | Command::SetBlob { key, .. } | Command::Rm { key } => {
+                Some(key)
+            }
+            Command::Sealed(_) => None,
+        }
+    }
 }
@@ -0,0 +1,69 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Codec applied to values written to the log.
+///
+/// The codec is recorded with every record, so generations written with
+/// different settings can be read back and compacted together.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+const ZSTD_LEVEL: i32 = 3;
+
+impl Compression {
+    pub(crate) fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+
+    /// Compresses `value` into base64 text, or returns `None` when that would not
+    /// make the record smaller.
+    pub(crate) fn encode(self, value: &str) -> Result<Option<String>> {
+        if self.is_none() {
+            return Ok(None);
+        }
+        let encoded = STANDARD.encode(self.compress(value.as_bytes())?);
+        Ok(if encoded.len() < value.len() {
+            Some(encoded)
+        } else {
+            None
+        })
+    }
+
+    /// Restores a value written with this codec.
+    pub(crate) fn decode(self, value: String) -> Result<String> {
+        if self.is_none() {
+            return Ok(value);
+        }
+        let compressed = STANDARD
+            .decode(value)
+            .map_err(|err| Error::CorruptValue(err.to_string()))?;
+        String::from_utf8(self.decompress(&compressed)?)
+            .map_err(|err| Error::CorruptValue(err.to_string()))
+    }
+
+    pub(crate) fn compress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Compression::None => bytes.to_vec(),
+            Compression::Zstd => zstd::encode_all(bytes, ZSTD_LEVEL)?,
+            Compression::Lz4 => lz4_flex::compress_prepend_size(bytes),
+        })
+    }
+
+    pub(crate) fn decompress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Zstd => {
+                zstd::decode_all(bytes).map_err(|err| Error::CorruptValue(err.to_string()))
+            }
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(bytes)
+                .map_err(|err| Error::CorruptValue(err.to_string())),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, fmt};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// A 256-bit XChaCha20-Poly1305 key, identified by `id` in everything it seals.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: u32,
+    key: [u8; 32],
+}
+
+impl EncryptionKey {
+    pub fn new(id: u32, key: [u8; 32]) -> Self {
+        EncryptionKey { id, key }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Ciphertext with the id of the key and the nonce it was sealed with.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Sealed {
+    key_id: u32,
+    nonce: String,
+    data: String,
+}
+
+impl Sealed {
+    pub(crate) fn key_id(&self) -> u32 {
+        self.key_id
+    }
+}
+
+/// What a sealed payload is and where it was written, authenticated along with
+/// it so that it cannot be passed off as another kind of data or moved to
+/// another file.
+///
+/// This does not order records within a generation: a sealed record copied
+/// within its own log file still opens, and dropping records from the end of
+/// the log, removing whole generations or restoring an older copy of the data
+/// directory is not detected.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Context {
+    /// A record of the `N.log` file of `gen`.
+    Record { gen: u64 },
+    /// The `line`th line of the hint file of `gen`.
+    Hint { gen: u64, line: u64 },
+    /// A value at `pos` in the `N.blob` file `file`.
+    Blob { file: u64, pos: u64 },
+    /// The `line`th line of an export.
+    Export { line: u64 },
+}
+
+impl Context {
+    fn aad(self, key_id: u32) -> Vec<u8> {
+        let (kind, fields) = match self {
+            Context::Record { gen } => (0u8, [gen, 0]),
+            Context::Hint { gen, line } => (1, [gen, line]),
+            Context::Blob { file, pos } => (2, [file, pos]),
+            Context::Export { line } => (3, [line, 0]),
+        };
+        let mut aad = vec![kind];
+        aad.extend_from_slice(&key_id.to_le_bytes());
+        for field in fields {
+            aad.extend_from_slice(&field.to_le_bytes());
+        }
+        aad
+    }
+}
+
+/// The key new data is sealed with, if any, and every key data can be opened with.
+#[derive(Clone, Default)]
+pub(crate) struct Keyring {
+    current: Option<u32>,
+    ciphers: HashMap<u32, XChaCha20Poly1305>,
+}
+
+impl Keyring {
+    pub(crate) fn new(current: Option<&EncryptionKey>, previous: &[EncryptionKey]) -> Self {
+        let ciphers = previous
+            .iter()
+            .chain(current)
+            .map(|key| (key.id, XChaCha20Poly1305::new(&key.key.into())))
+            .collect();
+        Keyring {
+            current: current.map(EncryptionKey::id),
+            ciphers,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ciphers.is_empty()
+    }
+
+    pub(crate) fn current(&self) -> Option<u32> {
+        self.current
+    }
+
+    /// Seals `plaintext`, written at `context`, with the current key, or returns
+    /// `None` without one.
+    pub(crate) fn seal(&self, plaintext: &[u8], context: Context) -> Result<Option<Sealed>> {
+        let key_id = match self.current {
+            Some(key_id) => key_id,
+            None => return Ok(None),
+        };
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: &context.aad(key_id),
+        };
+        let data = self.ciphers[&key_id]
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::Decryption)?;
+        Ok(Some(Sealed {
+            key_id,
+            nonce: STANDARD.encode(nonce),
+            data: STANDARD.encode(data),
+        }))
+    }
+
+    /// Opens data sealed at `context`, failing if it was sealed anywhere else.
+    pub(crate) fn open(&self, sealed: &Sealed, context: Context) -> Result<Vec<u8>> {
+        let cipher = self
+            .ciphers
+            .get(&sealed.key_id)
+            .ok_or(Error::MissingKey(sealed.key_id))?;
+        let nonce = STANDARD
+            .decode(&sealed.nonce)
+            .map_err(|_| Error::Decryption)?;
+        if nonce.len() != 24 {
+            return Err(Error::Decryption);
+        }
+        let data = STANDARD
+            .decode(&sealed.data)
+            .map_err(|_| Error::Decryption)?;
+        let payload = Payload {
+            msg: &data,
+            aad: &context.aad(sealed.key_id),
+        };
+        cipher
+            .decrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| Error::Decryption)
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("current", &self.current)
+            .field("keys", &self.ciphers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::BufWriterWithPos;
+use crate::command::BlobRef;
+use crate::encryption::{Context, Keyring, Sealed};
+use crate::{Compression, Error, Result};
+
+/// Size past which a new blob file is started.
+const BLOB_FILE_LIMIT: u64 = 64 * 1024 * 1024;
+/// Keeps the garbage count across processes, so that collection also happens
+/// when every write is made by a short-lived one.
+const GARBAGE_FILE: &str = "blobs.garbage";
+
+fn blob_path(path: &Path, file: u64) -> PathBuf {
+    path.join(format!("{}.blob", file))
+}
+
+/// Large values kept out of the log in append-only `N.blob` files, so that
+/// compaction only copies the small records pointing at them.
+#[derive(Debug)]
+pub(super) struct BlobStore {
+    path: PathBuf,
+    readers: HashMap<u64, File>,
+    /// The file blobs are appended to, opened on the first write.
+    writer: Option<(u64, BufWriterWithPos<File>)>,
+    next_file: u64,
+    /// Bytes of blobs overwritten or removed since the last collection.
+    garbage: u64,
+}
+
+impl BlobStore {
+    pub(super) fn open(path: &Path) -> Result<Self> {
+        let mut readers = HashMap::new();
+        for entry in fs::read_dir(path)? {
+            let file = entry?.path();
+            if file.extension() != Some("blob".as_ref()) {
+                continue;
+            }
+            let id = file
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .and_then(|stem| stem.parse::<u64>().ok());
+            if let Some(id) = id {
+                readers.insert(id, File::open(&file)?);
+            }
+        }
+
+        let garbage = match fs::read(path.join(GARBAGE_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(BlobStore {
+            path: path.to_owned(),
+            next_file: readers.keys().max().map_or(1, |id| id + 1),
+            readers,
+            writer: None,
+            garbage,
+        })
+    }
+
+    pub(super) fn garbage(&self) -> u64 {
+        self.garbage
+    }
+
+    /// Accounts for `len` bytes of blobs no record points to anymore.
+    pub(super) fn add_garbage(&mut self, len: u64) -> Result<()> {
+        self.garbage += len;
+        self.save_garbage()
+    }
+
+    pub(super) fn reset_garbage(&mut self) -> Result<()> {
+        if self.garbage == 0 {
+            return Ok(());
+        }
+        self.garbage = 0;
+        self.save_garbage()
+    }
+
+    fn save_garbage(&self) -> Result<()> {
+        let tmp_path = self.path.join(format!("{}.tmp", GARBAGE_FILE));
+        fs::write(&tmp_path, serde_json::to_vec(&self.garbage)?)?;
+        fs::rename(tmp_path, self.path.join(GARBAGE_FILE))?;
+        Ok(())
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.readers.is_empty()
+    }
+
+    pub(super) fn contains(&self, file: u64) -> bool {
+        self.readers.contains_key(&file)
+    }
+
+    /// Returns the file blobs are appended to. Before the first write that is
+    /// the newest file, which the write carries on with.
+    pub(super) fn active_file(&self) -> Option<u64> {
+        match &self.writer {
+            Some((file, _)) => Some(*file),
+            None => self.readers.keys().max().cloned(),
+        }
+    }
+
+    /// Returns the files which are no longer appended to.
+    pub(super) fn immutable_files(&self) -> Vec<u64> {
+        let active = self.active_file();
+        self.readers
+            .keys()
+            .filter(|&&file| Some(file) != active)
+            .cloned()
+            .collect()
+    }
+
+    pub(super) fn file_len(&self, file: u64) -> Result<u64> {
+        Ok(self.readers[&file].metadata()?.len())
+    }
+
+    pub(super) fn write(
+        &mut self,
+        value: &str,
+        compression: Compression,
+        keyring: &Keyring,
+    ) -> Result<BlobRef> {
+        let compressed = compression.compress(value.as_bytes())?;
+        let (mut bytes, compression) = if compressed.len() < value.len() {
+            (compressed, compression)
+        } else {
+            (value.as_bytes().to_vec(), Compression::None)
+        };
+
+        if self.writer.is_none() {
+            // Carry on with the newest file, so that every process writing a
+            // few blobs does not leave a file of its own
+            if let Some(file) = self.readers.keys().max().cloned() {
+                let mut writer = OpenOptions::new()
+                    .append(true)
+                    .open(blob_path(&self.path, file))?;
+                writer.seek(SeekFrom::End(0))?;
+                self.writer = Some((file, BufWriterWithPos::new(writer)?));
+            }
+        }
+        let full = match &self.writer {
+            Some((_, writer)) => writer.pos >= BLOB_FILE_LIMIT,
+            None => true,
+        };
+        if full {
+            self.start_file()?;
+        }
+
+        let (file, writer) = self.writer.as_mut().unwrap();
+        let pos = writer.pos;
+        let context = Context::Blob { file: *file, pos };
+        if let Some(sealed) = keyring.seal(&bytes, context)? {
+            bytes = serde_json::to_vec(&sealed)?;
+        }
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+        Ok(BlobRef {
+            file: *file,
+            pos,
+            len: bytes.len() as u64,
+            compression,
+            key_id: keyring.current(),
+        })
+    }
+
+    /// Starts a new file to append to, leaving the active one immutable.
+    pub(super) fn start_file(&mut self) -> Result<()> {
+        let file = self.next_file;
+        self.next_file += 1;
+        let path = blob_path(&self.path, file);
+        let writer =
+            BufWriterWithPos::new(OpenOptions::new().create(true).append(true).open(&path)?)?;
+        self.readers.insert(file, File::open(&path)?);
+        self.writer = Some((file, writer));
+        Ok(())
+    }
+
+    pub(super) fn read(&mut self, blob: &BlobRef, keyring: &Keyring) -> Result<String> {
+        let reader = self.readers.get_mut(&blob.file).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Can't find blob file: {}.blob", blob.file),
+            )
+        })?;
+        reader.seek(SeekFrom::Start(blob.pos))?;
+        let mut bytes = vec![0; blob.len as usize];
+        reader.read_exact(&mut bytes)?;
+
+        if blob.key_id.is_some() {
+            let sealed: Sealed = serde_json::from_slice(&bytes)?;
+            let context = Context::Blob {
+                file: blob.file,
+                pos: blob.pos,
+            };
+            bytes = keyring.open(&sealed, context)?;
+        }
+        String::from_utf8(blob.compression.decompress(&bytes)?)
+            .map_err(|err| Error::CorruptValue(err.to_string()))
+    }
+
+    pub(super) fn remove(&mut self, file: u64) -> Result<()> {
+        self.readers.remove(&file);
+        fs::remove_file(blob_path(&self.path, file))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use lru::LruCache;
+
+/// Recently read values, evicted least recently used first once their keys and
+/// values take more than `capacity` bytes.
+#[derive(Debug)]
+pub(super) struct ValueCache {
+    entries: LruCache<String, String>,
+    size: usize,
+    capacity: usize,
+    pub(super) hits: u64,
+    pub(super) misses: u64,
+}
+
+impl ValueCache {
+    pub(super) fn new(capacity: usize) -> Self {
+        ValueCache {
+            entries: LruCache::unbounded(),
+            size: 0,
+            capacity,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(super) fn get(&mut self, key: &str) -> Option<String> {
+        let value = self.entries.get(key).cloned();
+        if value.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        value
+    }
+
+    pub(super) fn insert(&mut self, key: String, value: String) {
+        let size = key.len() + value.len();
+        if size > self.capacity {
+            return;
+        }
+        self.size += size;
+        if let Some((key, old)) = self.entries.push(key, value) {
+            self.size -= key.len() + old.len();
+        }
+        while self.size > self.capacity {
+            match self.entries.pop_lru() {
+                Some((key, value)) => self.size -= key.len() + value.len(),
+                None => break,
+            }
+        }
+    }
+
+    pub(super) fn remove(&mut self, key: &str) {
+        if let Some((key, value)) = self.entries.pop_entry(key) {
+            self.size -= key.len() + value.len();
+        }
+    }
+}
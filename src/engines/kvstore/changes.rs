@@ -0,0 +1,249 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_json::Deserializer;
+
+use super::{log_path, BufReaderWithPos, KvStore};
+use crate::command::Command;
+use crate::engines::WatchEvent;
+use crate::{Error, Result};
+
+/// Low bits of a sequence number holding the offset within its generation.
+const OFFSET_BITS: u32 = 40;
+
+/// A mutation read back from the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The generation in the high bits and the offset just past the record in
+    /// the low 40, increasing in log order.
+    pub seq: u64,
+    pub event: WatchEvent,
+}
+
+impl KvStore {
+    /// Returns up to `limit` mutations in the log after sequence number `from`,
+    /// in order.
+    ///
+    /// Start from 0 and resume from the last `seq` seen. Compaction rewrites
+    /// the live keys as sets into a new generation, which a consumer starting
+    /// from 0 reads first and one resuming skips. The generations it replaces
+    /// are kept as `N.changes` files for consumers which were behind, see
+    /// [`KvStoreOptions::change_retention`]; resuming in one which is gone
+    /// fails with [`Error::Compacted`] and the consumer has to start over from
+    /// 0. Relocating a large value also repeats its set.
+    ///
+    /// [`KvStoreOptions::change_retention`]: crate::KvStoreOptions::change_retention
+    pub fn changes(&mut self, from: u64, limit: usize) -> Result<Vec<Change>> {
+        let mut gens: BTreeSet<u64> = self.readers.keys().cloned().collect();
+        gens.extend(history_gens(&self.path)?);
+
+        let (from_gen, offset) = if from == 0 {
+            // Older generations may be gone, the latest compaction has them all
+            let mut start = gens.first().cloned().unwrap_or(0);
+            for &gen in &gens {
+                if read_marker(&self.path, gen)?.is_some() {
+                    start = gen;
+                }
+            }
+            (start, 0)
+        } else {
+            let mut start = (from >> OFFSET_BITS, from & ((1 << OFFSET_BITS) - 1));
+            for &gen in &gens {
+                if gen > start.0 && read_marker(&self.path, gen)?.is_some_and(|end| from >= end) {
+                    start = (gen + 1, 0);
+                }
+            }
+            if start.1 > 0 && !gens.contains(&start.0) {
+                return Err(Error::Compacted(from));
+            }
+            start
+        };
+
+        let mut changes = Vec::new();
+        for gen in gens.range(from_gen..).cloned() {
+            // The consumer has read the generations this compaction replaces
+            if gen != from_gen && read_marker(&self.path, gen)?.is_some() {
+                continue;
+            }
+            let start = if gen == from_gen { offset } else { 0 };
+            let mut history;
+            let reader = match self.readers.get_mut(&gen) {
+                Some(reader) => reader,
+                None => match File::open(history_path(&self.path, gen)) {
+                    Ok(file) => {
+                        history = BufReaderWithPos::new(file)?;
+                        &mut history
+                    }
+                    // Removed by a compaction since the generations were listed.
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        return Err(Error::Compacted(from));
+                    }
+                    Err(err) => return Err(err.into()),
+                },
+            };
+            reader.seek(SeekFrom::Start(start))?;
+            let mut stream = Deserializer::from_reader(reader).into_iter::<Command>();
+            while changes.len() < limit {
+                let cmd = match stream.next() {
+                    Some(cmd) => cmd,
+                    None => break,
+                };
+                let end = start + stream.byte_offset() as u64;
+                let cmd = match cmd {
+                    // A record still being written by another process.
+                    Err(err) if err.is_eof() => break,
+                    cmd => cmd?,
+                };
+                let event = match cmd.unseal(&self.keyring, gen)? {
+                    Command::Set {
+                        key,
+                        value,
+                        compression,
+                    } => Some(WatchEvent::Set {
+                        key,
+                        value: compression.decode(value)?,
+                    }),
+                    // A blob collected after the record went stale, the value
+                    // was relocated by a later record.
+                    Command::SetBlob { blob, .. } if !self.blobs.contains(blob.file) => None,
+                    Command::SetBlob { key, blob } => Some(WatchEvent::Set {
+                        key,
+                        value: self.blobs.read(&blob, &self.keyring)?,
+                    }),
+                    Command::Rm { key } => Some(WatchEvent::Removed { key }),
+                    Command::Sealed(_) => unreachable!(),
+                };
+                if let Some(event) = event {
+                    changes.push(Change {
+                        seq: seq(gen, end)?,
+                        event,
+                    });
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Returns the sequence number just past the last record in the generations
+    /// before `compaction_gen`, which that compaction replaces.
+    pub(super) fn compacted_end(&self, compaction_gen: u64) -> Result<u64> {
+        let mut gens: Vec<u64> = self
+            .readers
+            .keys()
+            .filter(|&&gen| gen < compaction_gen)
+            .cloned()
+            .collect();
+        gens.sort_unstable();
+        for gen in gens.into_iter().rev() {
+            // Nothing was written since the previous compaction.
+            if let Some(end) = read_marker(&self.path, gen)? {
+                return Ok(end);
+            }
+            let len = fs::metadata(log_path(&self.path, gen))?.len();
+            if len > 0 {
+                return seq(gen, len);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Returns the sequence number of offset `end` of `gen`, failing rather than
+/// wrapping around once either is out of range.
+fn seq(gen: u64, end: u64) -> Result<u64> {
+    if gen >> (64 - OFFSET_BITS) != 0 || end >> OFFSET_BITS != 0 {
+        return Err(Error::SequenceOverflow(gen, end));
+    }
+    Ok(gen << OFFSET_BITS | end)
+}
+
+fn history_path(path: &Path, gen: u64) -> PathBuf {
+    path.join(format!("{}.changes", gen))
+}
+
+/// Lists the generations kept for their changes only, oldest first.
+fn history_gens(path: &Path) -> Result<Vec<u64>> {
+    let mut gens = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension() != Some("changes".as_ref()) {
+            continue;
+        }
+        if let Some(gen) = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .and_then(|stem| stem.parse().ok())
+        {
+            gens.push(gen);
+        }
+    }
+    gens.sort_unstable();
+    Ok(gens)
+}
+
+/// Keeps the log of `gen`, replaced by a compaction, as a `N.changes` file.
+pub(super) fn retire(path: &Path, gen: u64) -> Result<()> {
+    fs::rename(log_path(path, gen), history_path(path, gen))?;
+    Ok(())
+}
+
+/// Removes the oldest `N.changes` files while they take more than `retention`
+/// bytes, keeping those of `gen` and later.
+pub(super) fn trim_history(path: &Path, gen: u64, retention: u64) -> Result<()> {
+    let gens = history_gens(path)?;
+    let mut total = 0;
+    let mut lens = Vec::with_capacity(gens.len());
+    for &gen in &gens {
+        let len = fs::metadata(history_path(path, gen))?.len();
+        total += len;
+        lens.push(len);
+    }
+    for (old_gen, len) in gens.into_iter().zip(lens) {
+        if total <= retention || old_gen >= gen {
+            break;
+        }
+        fs::remove_file(history_path(path, old_gen))?;
+        remove_marker(path, old_gen)?;
+        total -= len;
+    }
+    Ok(())
+}
+
+fn marker_path(path: &Path, gen: u64) -> PathBuf {
+    path.join(format!("{}.compacted", gen))
+}
+
+/// Records next to compacted generation `gen` the sequence number up to which
+/// it replaces the log.
+pub(super) fn write_marker(path: &Path, gen: u64, end: u64) -> Result<()> {
+    let tmp_path = path.join(format!("{}.compacted.tmp", gen));
+    let mut file = File::create(&tmp_path)?;
+    serde_json::to_writer(&mut file, &end)?;
+    file.flush()?;
+    fs::rename(tmp_path, marker_path(path, gen))?;
+    Ok(())
+}
+
+/// Returns the sequence number recorded by `write_marker`, or `None` if `gen`
+/// was not written by a compaction.
+fn read_marker(path: &Path, gen: u64) -> Result<Option<u64>> {
+    match fs::read(marker_path(path, gen)) {
+        Ok(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Removes the marker of `gen`, if any.
+fn remove_marker(path: &Path, gen: u64) -> Result<()> {
+    match fs::remove_file(marker_path(path, gen)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use super::{map_log, verify, KvStore};
+use crate::command::Command;
+use crate::Result;
+
+/// A single decoded span of a `N.log` file.
+#[derive(Debug)]
+pub struct LogEntry {
+    pub gen: u64,
+    pub offset: u64,
+    pub len: u64,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EntryKind {
+    Set {
+        key: String,
+        value: String,
+    },
+    /// A value kept in a blob file.
+    SetBlob {
+        key: String,
+        file: u64,
+        len: u64,
+    },
+    Rm {
+        key: String,
+    },
+    /// An encrypted record, which can only be decoded by an open store.
+    Sealed {
+        key_id: u32,
+    },
+    /// Bytes which could not be decoded as a record.
+    Corrupt,
+}
+
+impl LogEntry {
+    pub fn key(&self) -> Option<&str> {
+        match &self.kind {
+            EntryKind::Set { key, .. } | EntryKind::SetBlob { key, .. } | EntryKind::Rm { key } => {
+                Some(key)
+            }
+            EntryKind::Sealed { .. } | EntryKind::Corrupt => None,
+        }
+    }
+}
+
+impl KvStore {
+    /// Decodes the records of every generation in `path`, or only of `gen` if given,
+    /// and calls `f` with each of them in log order.
+    ///
+    /// With `key`, only the records of that key are decoded and passed on.
+    pub fn dump(
+        path: impl Into<PathBuf>,
+        gen: Option<u64>,
+        key: Option<&str>,
+        mut f: impl FnMut(LogEntry) -> Result<()>,
+    ) -> Result<()> {
+        let path = path.into();
+        let gens = match gen {
+            Some(gen) => vec![gen],
+            None => Self::load_gens_list(&path)?,
+        };
+
+        for gen in gens {
+            let map = map_log(&path, gen)?;
+            for segment in verify::segments(map.as_deref().unwrap_or_default()) {
+                let (range, kind) = match segment {
+                    verify::Segment::Record(range, cmd) => {
+                        if key.is_some() && cmd.key() != key {
+                            continue;
+                        }
+                        (range, EntryKind::from(cmd))
+                    }
+                    _ if key.is_some() => continue,
+                    verify::Segment::Corrupt(range) => (range, EntryKind::Corrupt),
+                };
+                f(LogEntry {
+                    gen,
+                    offset: range.start,
+                    len: range.end - range.start,
+                    kind,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Command> for EntryKind {
+    fn from(cmd: Command) -> Self {
+        match cmd {
+            Command::Set {
+                key,
+                value,
+                compression,
+            } => match compression.decode(value) {
+                Ok(value) => EntryKind::Set { key, value },
+                Err(_) => EntryKind::Corrupt,
+            },
+            Command::SetBlob { key, blob } => EntryKind::SetBlob {
+                key,
+                file: blob.file,
+                len: blob.len,
+            },
+            Command::Rm { key } => EntryKind::Rm { key },
+            Command::Sealed(sealed) => EntryKind::Sealed {
+                key_id: sealed.key_id(),
+            },
+        }
+    }
+}
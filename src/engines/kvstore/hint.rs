@@ -0,0 +1,142 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Deserializer;
+use tracing::warn;
+
+use super::{index::Index, Pos};
+use crate::encryption::{Context, Keyring, Sealed};
+use crate::Result;
+
+/// Location of a live record, as written next to a compacted generation so that
+/// opening the store does not have to decode the records themselves.
+#[derive(Serialize, Deserialize, Debug)]
+struct Hint {
+    key: String,
+    gen: u64,
+    pos: u64,
+    len: u64,
+}
+
+/// A line of a hint file, encrypted when the store has an encryption key.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum HintLine {
+    Plain(Hint),
+    Sealed(Sealed),
+}
+
+pub(super) fn hint_path(path: &Path, gen: u64) -> PathBuf {
+    path.join(format!("{}.hint", gen))
+}
+
+/// Writes the hint file of a compacted generation.
+///
+/// The file is written under a temporary name and renamed into place by `finish`,
+/// so a hint file is either complete or missing.
+pub(super) struct HintWriter {
+    writer: BufWriter<File>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+    keyring: Keyring,
+    gen: u64,
+    lines: u64,
+}
+
+impl HintWriter {
+    pub(super) fn create(path: &Path, gen: u64, keyring: &Keyring) -> Result<Self> {
+        let tmp_path = path.join(format!("{}.hint.tmp", gen));
+        Ok(HintWriter {
+            writer: BufWriter::new(File::create(&tmp_path)?),
+            tmp_path,
+            path: hint_path(path, gen),
+            keyring: keyring.clone(),
+            gen,
+            lines: 0,
+        })
+    }
+
+    pub(super) fn append(&mut self, key: &str, pos: Pos) -> Result<()> {
+        let hint = Hint {
+            key: key.to_owned(),
+            gen: pos.gen,
+            pos: pos.pos,
+            len: pos.len,
+        };
+        let context = Context::Hint {
+            gen: self.gen,
+            line: self.lines,
+        };
+        let line = match self.keyring.seal(&serde_json::to_vec(&hint)?, context)? {
+            Some(sealed) => HintLine::Sealed(sealed),
+            None => HintLine::Plain(hint),
+        };
+        self.lines += 1;
+        serde_json::to_writer(&mut self.writer, &line)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub(super) fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        fs::rename(self.tmp_path, self.path)?;
+        Ok(())
+    }
+}
+
+/// Replays the hint file of `gen` into `index`, returning the number of stale
+/// bytes, or `None` if there is no usable hint file and the log has to be read.
+pub(super) fn load(
+    path: &Path,
+    gen: u64,
+    index: &mut Index,
+    keyring: &Keyring,
+) -> Result<Option<u64>> {
+    let file = match File::open(hint_path(path, gen)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let hints: Result<Vec<Hint>> = Deserializer::from_reader(BufReader::new(file))
+        .into_iter()
+        .zip(0..)
+        .map(|(line, number)| match line? {
+            HintLine::Plain(hint) => Ok(hint),
+            HintLine::Sealed(sealed) => {
+                let context = Context::Hint { gen, line: number };
+                Ok(serde_json::from_slice(&keyring.open(&sealed, context)?)?)
+            }
+        })
+        .collect();
+    let hints = match hints {
+        Ok(hints) => hints,
+        Err(err) => {
+            warn!(gen, error = %err, "ignoring unreadable hint file");
+            return Ok(None);
+        }
+    };
+
+    let mut compaction = 0;
+    for hint in hints {
+        let pos = (hint.gen, hint.pos..hint.pos + hint.len).into();
+        if let Some(old_cmd) = index.insert(hint.key, pos)? {
+            compaction += old_cmd.len;
+        }
+    }
+
+    Ok(Some(compaction))
+}
+
+/// Removes the hint file of `gen`, if any.
+pub(super) fn remove(path: &Path, gen: u64) -> Result<()> {
+    match fs::remove_file(hint_path(path, gen)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
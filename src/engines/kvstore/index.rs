@@ -0,0 +1,577 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    iter::Peekable,
+    mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use serde_json::Deserializer;
+
+use super::Pos;
+use crate::Result;
+
+const INDEX_FILE: &str = "kvs.index";
+/// Number of entries per block of a run file. Only the first key of each block
+/// is kept in memory.
+const BLOCK_LEN: usize = 64;
+/// Number of changes kept in memory before they are written as a new run.
+const DELTA_LIMIT: usize = 16 * 1024;
+/// A new run is merged with the runs before it until they hold more than this
+/// many times its entries, so run sizes grow geometrically and every entry is
+/// rewritten a logarithmic number of times.
+const MERGE_RATIO: usize = 4;
+
+/// Maps every live key to the position of its latest `Set` record.
+#[derive(Debug)]
+pub(super) enum Index {
+    Memory(BTreeMap<String, Pos>),
+    Disk(Box<DiskIndex>),
+}
+
+/// Log position up to which an index file reflects the records, and the stale
+/// byte count at that point. Records after it are replayed on open.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub(super) struct Checkpoint {
+    gen: u64,
+    pos: u64,
+    stale: u64,
+    len: usize,
+}
+
+impl Checkpoint {
+    pub(super) fn at(gen: u64, pos: u64, stale: u64) -> Self {
+        Checkpoint {
+            gen,
+            pos,
+            stale,
+            len: 0,
+        }
+    }
+
+    pub(super) fn gen(&self) -> u64 {
+        self.gen
+    }
+
+    pub(super) fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    pub(super) fn stale(&self) -> u64 {
+        self.stale
+    }
+}
+
+/// Contents of the index file: the checkpoint and the ids of the run files
+/// holding the entries, oldest first.
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    checkpoint: Checkpoint,
+    runs: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    key: String,
+    /// `None` for a key removed since an older run was written.
+    pos: Option<Pos>,
+}
+
+impl Index {
+    pub(super) fn get(&mut self, key: &str) -> Result<Option<Pos>> {
+        match self {
+            Index::Memory(map) => Ok(map.get(key).cloned()),
+            Index::Disk(index) => index.get(key),
+        }
+    }
+
+    pub(super) fn insert(&mut self, key: String, pos: Pos) -> Result<Option<Pos>> {
+        match self {
+            Index::Memory(map) => Ok(map.insert(key, pos)),
+            Index::Disk(index) => index.insert(key, pos),
+        }
+    }
+
+    pub(super) fn remove(&mut self, key: &str) -> Result<Option<Pos>> {
+        match self {
+            Index::Memory(map) => Ok(map.remove(key)),
+            Index::Disk(index) => index.remove(key),
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        match self {
+            Index::Memory(map) => map.len(),
+            Index::Disk(index) => index.len,
+        }
+    }
+
+    /// Calls `f` for every entry in key order.
+    pub(super) fn scan(&self, mut f: impl FnMut(&str, Pos) -> Result<()>) -> Result<()> {
+        match self {
+            Index::Memory(map) => map.iter().try_for_each(|(key, &pos)| f(key, pos)),
+            Index::Disk(index) => index.merged().try_for_each(|entry| match entry? {
+                (key, Some(pos)) => f(&key, pos),
+                (_, None) => Ok(()),
+            }),
+        }
+    }
+
+    /// Replaces the position of every entry, in key order, by the one returned by `f`.
+    ///
+    /// `checkpoint` is the log position the rebuilt index reflects. The rebuilt
+    /// index is only put in place by [`Index::commit`], once the new positions
+    /// are durable.
+    pub(super) fn rebuild(
+        &mut self,
+        mut f: impl FnMut(&str, Pos) -> Result<Pos>,
+        checkpoint: Checkpoint,
+    ) -> Result<()> {
+        match self {
+            Index::Memory(map) => map
+                .iter_mut()
+                .try_for_each(|(key, pos)| f(key, *pos).map(|new_pos| *pos = new_pos)),
+            Index::Disk(index) => index.rebuild(f, checkpoint),
+        }
+    }
+
+    /// Called after every written record with the current log position; writes
+    /// pending changes to the index once there are enough of them.
+    pub(super) fn checkpoint(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        match self {
+            Index::Disk(index) if index.persist && index.delta.len() >= DELTA_LIMIT => {
+                index.flush(checkpoint)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes every pending change to the index, called once the log is replayed.
+    pub(super) fn flush(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        match self {
+            Index::Disk(index) if index.persist && !index.delta.is_empty() => {
+                index.flush(checkpoint)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Puts in place the index written by [`Index::rebuild`].
+    pub(super) fn commit(&mut self) -> Result<()> {
+        match self {
+            Index::Memory(_) => Ok(()),
+            Index::Disk(index) => index.commit(),
+        }
+    }
+}
+
+/// Index kept in sorted run files in the data directory, with only the first
+/// key of every block, recently used blocks and not yet written changes in
+/// memory.
+///
+/// Changes are written as a new run, which is merged with the newest runs
+/// while they are not much larger, instead of rewriting every entry.
+#[derive(Debug)]
+pub(super) struct DiskIndex {
+    dir: PathBuf,
+    /// Oldest first, the entry of the newest run having a key wins.
+    runs: Vec<Run>,
+    next_run: u64,
+    cache: BlockCache,
+    /// Changes since the newest run was written, `None` marking a removed key.
+    delta: BTreeMap<String, Option<Pos>>,
+    len: usize,
+    persist: bool,
+    /// Run written by `rebuild` and the checkpoint it reflects, until committed.
+    pending: Option<(Run, Checkpoint)>,
+}
+
+impl DiskIndex {
+    /// Opens the index of the data directory in `dir`, returning the log
+    /// position it reflects, if any.
+    ///
+    /// Without `persist` the index is never written, changes are kept in memory.
+    pub(super) fn open(
+        dir: &Path,
+        cache_blocks: usize,
+        persist: bool,
+    ) -> Result<(Self, Option<Checkpoint>)> {
+        let mut index = DiskIndex {
+            dir: dir.to_owned(),
+            runs: Vec::new(),
+            next_run: 1,
+            cache: LruCache::new(NonZeroUsize::new(cache_blocks).unwrap_or(NonZeroUsize::MIN)),
+            delta: BTreeMap::new(),
+            len: 0,
+            persist,
+            pending: None,
+        };
+
+        let manifest: Manifest = match File::open(dir.join(INDEX_FILE)) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((index, None)),
+            Err(err) => return Err(err.into()),
+        };
+        for &id in &manifest.runs {
+            index.runs.push(Run::open(dir, id)?);
+        }
+        index.next_run = manifest.runs.iter().max().map_or(1, |id| id + 1);
+        if persist {
+            // Left behind by a merge interrupted before the index file was replaced
+            for id in run_ids(dir)? {
+                if !manifest.runs.contains(&id) {
+                    fs::remove_file(run_path(dir, id))?;
+                }
+            }
+        }
+
+        index.len = manifest.checkpoint.len;
+        Ok((index, Some(manifest.checkpoint)))
+    }
+
+    /// Removes the index files of the data directory in `dir`, if any.
+    pub(super) fn remove_files(dir: &Path) -> Result<()> {
+        match fs::remove_file(dir.join(INDEX_FILE)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        for id in run_ids(dir)? {
+            fs::remove_file(run_path(dir, id))?;
+        }
+        Ok(())
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<Pos>> {
+        if let Some(&pos) = self.delta.get(key) {
+            return Ok(pos);
+        }
+        for run in self.runs.iter().rev() {
+            if let Some(pos) = run.find(key, &mut self.cache)? {
+                return Ok(pos);
+            }
+        }
+        Ok(None)
+    }
+
+    fn insert(&mut self, key: String, pos: Pos) -> Result<Option<Pos>> {
+        let old = self.get(&key)?;
+        if old.is_none() {
+            self.len += 1;
+        }
+        self.delta.insert(key, Some(pos));
+        Ok(old)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Option<Pos>> {
+        let old = self.get(key)?;
+        if old.is_some() {
+            self.len -= 1;
+            self.delta.insert(key.to_owned(), None);
+        }
+        Ok(old)
+    }
+
+    /// Iterates over the delta and the runs merged, removed keys included.
+    fn merged(&self) -> Merge<'_> {
+        self.merged_from(0)
+    }
+
+    /// Like `merged`, over the delta and the runs from `first` on only.
+    fn merged_from(&self, first: usize) -> Merge<'_> {
+        let mut sources: Vec<Source<'_>> = vec![Box::new(
+            self.delta.iter().map(|(key, &pos)| Ok((key.clone(), pos))),
+        )];
+        for run in self.runs[first..].iter().rev() {
+            sources.push(Box::new(run.entries()));
+        }
+        Merge::new(sources)
+    }
+
+    /// Writes the delta as a new run, merged with the newest runs unless they
+    /// are more than `MERGE_RATIO` times larger, and records the runs with
+    /// `checkpoint` in the index file.
+    fn flush(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        let mut first = self.runs.len();
+        let mut size = self.delta.len();
+        while first > 0 && size * MERGE_RATIO >= self.runs[first - 1].len {
+            first -= 1;
+            size += self.runs[first].len;
+        }
+
+        // Removed keys have nothing left to shadow in the oldest run
+        let entries = self
+            .merged_from(first)
+            .filter(|entry| first > 0 || !matches!(entry, Ok((_, None))));
+        let run = Run::write(&self.dir, self.next_run, entries)?;
+        self.next_run += 1;
+        self.delta.clear();
+        let merged = self.runs.split_off(first);
+        self.runs.push(run);
+        self.save(checkpoint)?;
+        for run in merged {
+            fs::remove_file(run_path(&self.dir, run.id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every entry, with its position replaced by the one returned by
+    /// `f`, as a single run to be put in place by `commit`.
+    fn rebuild(
+        &mut self,
+        mut f: impl FnMut(&str, Pos) -> Result<Pos>,
+        checkpoint: Checkpoint,
+    ) -> Result<()> {
+        let entries = self.merged().filter_map(|entry| match entry {
+            Ok((key, Some(pos))) => Some(f(&key, pos).map(|pos| (key, Some(pos)))),
+            Ok((_, None)) => None,
+            Err(err) => Some(Err(err)),
+        });
+        let run = Run::write(&self.dir, self.next_run, entries)?;
+        self.next_run += 1;
+        self.pending = Some((run, checkpoint));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if let Some((run, checkpoint)) = self.pending.take() {
+            let replaced = mem::replace(&mut self.runs, vec![run]);
+            self.delta.clear();
+            self.cache.clear();
+            self.save(checkpoint)?;
+            for run in replaced {
+                fs::remove_file(run_path(&self.dir, run.id))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the index file with the current runs.
+    fn save(&self, checkpoint: Checkpoint) -> Result<()> {
+        let manifest = Manifest {
+            checkpoint: Checkpoint {
+                len: self.len,
+                ..checkpoint
+            },
+            runs: self.runs.iter().map(|run| run.id).collect(),
+        };
+        let tmp_path = self.dir.join(format!("{}.tmp", INDEX_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &manifest)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(tmp_path, self.dir.join(INDEX_FILE))?;
+        Ok(())
+    }
+}
+
+fn run_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.index", id))
+}
+
+/// Returns the ids of the run files in `dir`.
+fn run_ids(dir: &Path) -> Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some("index".as_ref()) {
+            continue;
+        }
+        if let Some(id) = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .and_then(|stem| stem.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+/// Recently read blocks, by run id and block number.
+type BlockCache = LruCache<(u64, usize), Vec<(String, Option<Pos>)>>;
+
+/// A sorted run of entries in a `N.index` file.
+///
+/// Every read goes through the file opened with the run, so a reader keeps
+/// seeing the same entries after a writer replaced the run.
+#[derive(Debug)]
+struct Run {
+    id: u64,
+    file: File,
+    blocks: Vec<(String, u64)>,
+    len: usize,
+}
+
+impl Run {
+    fn open(dir: &Path, id: u64) -> Result<Self> {
+        let file = File::open(run_path(dir, id))?;
+        let mut blocks = Vec::new();
+        let mut len = 0;
+        {
+            let mut stream = Deserializer::from_reader(BufReader::new(RunReader::new(&file, 0)))
+                .into_iter::<Entry>();
+            let mut offset = 0;
+            while let Some(entry) = stream.next() {
+                let entry = entry?;
+                if len % BLOCK_LEN == 0 {
+                    blocks.push((entry.key, offset));
+                }
+                len += 1;
+                offset = stream.byte_offset() as u64;
+            }
+        }
+
+        Ok(Run {
+            id,
+            file,
+            blocks,
+            len,
+        })
+    }
+
+    fn write(
+        dir: &Path,
+        id: u64,
+        entries: impl Iterator<Item = Result<(String, Option<Pos>)>>,
+    ) -> Result<Self> {
+        let path = run_path(dir, id);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut blocks = Vec::new();
+        let mut len = 0;
+        let mut offset = 0;
+        for entry in entries {
+            let (key, pos) = entry?;
+            if len % BLOCK_LEN == 0 {
+                blocks.push((key.clone(), offset));
+            }
+            let mut line = serde_json::to_vec(&Entry { key, pos })?;
+            line.push(b'\n');
+            writer.write_all(&line)?;
+            offset += line.len() as u64;
+            len += 1;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        Ok(Run {
+            id,
+            file: File::open(&path)?,
+            blocks,
+            len,
+        })
+    }
+
+    /// Looks `key` up, `None` if the run has no entry for it.
+    fn find(&self, key: &str, cache: &mut BlockCache) -> Result<Option<Option<Pos>>> {
+        let block = match self
+            .blocks
+            .binary_search_by(|(first_key, _)| first_key.as_str().cmp(key))
+        {
+            Ok(block) => block,
+            Err(0) => return Ok(None),
+            Err(block) => block - 1,
+        };
+        let entries = cache.try_get_or_insert((self.id, block), || self.read_block(block))?;
+
+        Ok(entries
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|&(_, pos)| pos))
+    }
+
+    fn read_block(&self, block: usize) -> Result<Vec<(String, Option<Pos>)>> {
+        let reader = BufReader::new(RunReader::new(&self.file, self.blocks[block].1));
+        Deserializer::from_reader(reader)
+            .into_iter::<Entry>()
+            .take(BLOCK_LEN)
+            .map(|entry| entry.map(Entry::into_pair).map_err(Into::into))
+            .collect()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = Result<(String, Option<Pos>)>> + '_ {
+        Deserializer::from_reader(BufReader::new(RunReader::new(&self.file, 0)))
+            .into_iter::<Entry>()
+            .map(|entry| entry.map(Entry::into_pair).map_err(Into::into))
+    }
+}
+
+/// Reads a run file from an offset of its own, so that reads of blocks and
+/// scans through the shared handle can be interleaved.
+struct RunReader<'a> {
+    file: &'a File,
+    pos: u64,
+}
+
+impl<'a> RunReader<'a> {
+    fn new(file: &'a File, pos: u64) -> Self {
+        RunReader { file, pos }
+    }
+}
+
+impl Read for RunReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.file;
+        file.seek(SeekFrom::Start(self.pos))?;
+        let len = file.read(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Entry {
+    fn into_pair(self) -> (String, Option<Pos>) {
+        (self.key, self.pos)
+    }
+}
+
+type Source<'a> = Box<dyn Iterator<Item = Result<(String, Option<Pos>)>> + 'a>;
+
+/// Merges sorted sources, ordered by precedence, into one sorted stream where
+/// each key appears once with the entry of the first source having it.
+struct Merge<'a> {
+    sources: Vec<Peekable<Source<'a>>>,
+}
+
+impl<'a> Merge<'a> {
+    fn new(sources: Vec<Source<'a>>) -> Self {
+        Merge {
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+        }
+    }
+}
+
+impl Iterator for Merge<'_> {
+    type Item = Result<(String, Option<Pos>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut next: Option<(usize, String)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Err(_)) => return source.next(),
+                Some(Ok((key, _))) if next.as_ref().is_none_or(|(_, next_key)| key < next_key) => {
+                    next = Some((i, key.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        let (i, key) = next?;
+        let entry = self.sources[i].next();
+        for source in &mut self.sources {
+            while let Some(Ok((other_key, _))) = source.peek() {
+                if *other_key != key {
+                    break;
+                }
+                source.next();
+            }
+        }
+        entry
+    }
+}
@@ -0,0 +1,736 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+    time::Instant,
+};
+
+use memmap2::Mmap;
+
+use super::{lock_dir, WatchEvent, Watchers};
+use crate::command::{BlobRef, Command};
+use crate::encryption::Keyring;
+pub use crate::error::Error;
+use crate::Result;
+use crate::{Compression, KvsEngine};
+use serde::{Deserialize, Serialize};
+use serde_json::Deserializer;
+use tracing::{debug, info};
+
+mod blob;
+mod cache;
+mod changes;
+mod dump;
+mod hint;
+mod index;
+mod options;
+mod stats;
+mod verify;
+
+use blob::BlobStore;
+use cache::ValueCache;
+use hint::HintWriter;
+use index::{Checkpoint, DiskIndex, Index};
+use stats::CompactionStats;
+
+pub use changes::Change;
+pub use dump::{EntryKind, LogEntry};
+pub use options::{IndexMode, KvStoreOptions};
+pub use stats::{GenerationStats, Stats};
+pub use verify::{GenerationReport, VerifyReport};
+
+const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+const BLOB_GARBAGE_THRESHOLD: u64 = 64 * 1024 * 1024;
+const OPEN_RETRIES: u32 = 3;
+
+#[derive(Debug)]
+pub struct KvStore {
+    blobs: BlobStore,
+    blob_threshold: Option<usize>,
+    cache: Option<ValueCache>,
+    change_retention: u64,
+    compaction: u64,
+    compactions: CompactionStats,
+    compression: Compression,
+    keyring: Keyring,
+    path: PathBuf,
+    current_gen: u64,
+    index: Index,
+    readers: HashMap<u64, BufReaderWithPos<File>>,
+    /// Mapped generations which are no longer written to, if mapping is enabled.
+    maps: Option<HashMap<u64, Mmap>>,
+    writer: Option<BufWriterWithPos<File>>,
+    watchers: Watchers,
+    // Held for the lifetime of a writable store, the lock is released on drop.
+    _lock: Option<File>,
+}
+
+impl KvStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        KvStoreOptions::new().open(path)
+    }
+
+    /// Opens the store in `path` for reads only.
+    ///
+    /// No files are created and the directory lock is not taken, so this works
+    /// while another process has the store open for writing. The returned store
+    /// sees the records written before the call; `set` and `remove` fail with
+    /// `Error::ReadOnly`.
+    pub fn open_read_only(path: impl Into<PathBuf>) -> Result<Self> {
+        KvStoreOptions::new().read_only(true).open(path)
+    }
+
+    fn open_with(path: PathBuf, options: &KvStoreOptions) -> Result<Self> {
+        if options.encryption_key.is_some() && options.index_mode != IndexMode::Memory {
+            return Err(Error::EncryptedDiskIndex);
+        }
+        if options.read_only {
+            let mut attempts = 0;
+            loop {
+                // A concurrent compaction may remove generations while they are replayed.
+                match Self::replay(path.clone(), options) {
+                    Err(Error::IO(err))
+                        if err.kind() == io::ErrorKind::NotFound && attempts < OPEN_RETRIES =>
+                    {
+                        attempts += 1;
+                    }
+                    result => return result,
+                }
+            }
+        }
+
+        fs::create_dir_all(&path)?;
+        let lock = lock_dir(&path)?;
+        if options.index_mode == IndexMode::Memory {
+            // It would not be kept up to date
+            DiskIndex::remove_files(&path)?;
+        }
+
+        let mut kvstore = Self::replay(path, options)?;
+        kvstore.writer = Some(Self::new_log_file(
+            &kvstore.path,
+            kvstore.current_gen,
+            &mut kvstore.readers,
+        )?);
+        kvstore._lock = Some(lock);
+
+        Ok(kvstore)
+    }
+
+    fn replay(path: PathBuf, options: &KvStoreOptions) -> Result<Self> {
+        let gens = Self::load_gens_list(&path)?;
+
+        let (mut index, checkpoint) = match options.index_mode {
+            IndexMode::Memory => (Index::Memory(BTreeMap::new()), None),
+            IndexMode::OnDisk { cache_blocks } => {
+                let (index, checkpoint) = DiskIndex::open(&path, cache_blocks, !options.read_only)?;
+                (Index::Disk(Box::new(index)), checkpoint)
+            }
+        };
+
+        let keyring = Keyring::new(options.encryption_key.as_ref(), &options.previous_keys);
+        let mut readers = HashMap::new();
+        let mut compaction = checkpoint.map_or(0, |checkpoint| checkpoint.stale());
+
+        let mut replayed = None;
+        for &gen in &gens {
+            let mut reader = BufReaderWithPos::new(File::open(log_path(&path, gen))?)?;
+            let end = match checkpoint {
+                Some(checkpoint) if gen < checkpoint.gen() => None,
+                Some(checkpoint) if gen == checkpoint.gen() => Some(Self::load(
+                    gen,
+                    &mut reader,
+                    checkpoint.pos(),
+                    &mut index,
+                    &mut compaction,
+                    &keyring,
+                    options.read_only,
+                )?),
+                _ => match hint::load(&path, gen, &mut index, &keyring)? {
+                    Some(stale) => {
+                        compaction += stale;
+                        let end = reader.seek(SeekFrom::End(0))?;
+                        index.checkpoint(Checkpoint::at(gen, end, compaction))?;
+                        Some(end)
+                    }
+                    None => Some(Self::load(
+                        gen,
+                        &mut reader,
+                        0,
+                        &mut index,
+                        &mut compaction,
+                        &keyring,
+                        options.read_only,
+                    )?),
+                },
+            };
+            if let Some(end) = end {
+                replayed = Some(Checkpoint::at(gen, end, compaction));
+            }
+            readers.insert(gen, reader);
+        }
+        if let Some(checkpoint) = replayed {
+            index.flush(checkpoint)?;
+        }
+
+        let current_gen = gens.last().unwrap_or(&0) + 1;
+        let blobs = BlobStore::open(&path)?;
+        let compactions = CompactionStats::load(&path)?;
+        let maps = if options.mmap {
+            let mut maps = HashMap::new();
+            for &gen in &gens {
+                if let Some(map) = map_log(&path, gen)? {
+                    maps.insert(gen, map);
+                }
+            }
+            Some(maps)
+        } else {
+            None
+        };
+
+        info!(
+            path = %path.display(),
+            generations = gens.len(),
+            keys = index.len(),
+            stale_bytes = compaction,
+            read_only = options.read_only,
+            index_mode = ?options.index_mode,
+            compression = ?options.compression,
+            "opened store"
+        );
+
+        Ok(Self {
+            index,
+            path,
+            compaction,
+            compactions,
+            blobs,
+            blob_threshold: options.blob_threshold,
+            cache: options.value_cache.map(ValueCache::new),
+            change_retention: options.change_retention,
+            compression: options.compression,
+            keyring,
+            current_gen,
+            writer: None,
+            readers,
+            maps,
+            watchers: Watchers::default(),
+            _lock: None,
+        })
+    }
+
+    /// Replays the records of a generation starting at offset `from` into `index`,
+    /// adding stale bytes to `compaction` and returning the offset replay ended
+    /// at. With `allow_torn_tail`, a record cut off at the end of the file, as
+    /// seen while a writer is appending, ends the generation instead of failing.
+    fn load(
+        gen: u64,
+        reader: &mut BufReaderWithPos<File>,
+        from: u64,
+        index: &mut Index,
+        compaction: &mut u64,
+        keyring: &Keyring,
+        allow_torn_tail: bool,
+    ) -> Result<u64> {
+        let mut pos = reader.seek(SeekFrom::Start(from))?;
+        let mut stream = Deserializer::from_reader(reader).into_iter::<Command>();
+        while let Some(cmd) = stream.next() {
+            let new_pos = from + stream.byte_offset() as u64;
+            let cmd = match cmd {
+                Err(err) if allow_torn_tail && err.is_eof() => break,
+                cmd => cmd?,
+            };
+            match cmd.unseal(keyring, gen)? {
+                Command::Set { key, .. } | Command::SetBlob { key, .. } => {
+                    if let Some(old_cmd) = index.insert(key, (gen, pos..new_pos).into())? {
+                        *compaction += old_cmd.len;
+                    }
+                }
+                Command::Rm { key } => {
+                    if let Some(old_cmd) = index.remove(&key)? {
+                        *compaction += old_cmd.len;
+                    }
+                    *compaction += new_pos - pos;
+                }
+                Command::Sealed(_) => unreachable!(),
+            }
+            pos = new_pos;
+            index.checkpoint(Checkpoint::at(gen, pos, *compaction))?;
+        }
+
+        Ok(pos)
+    }
+
+    /// Rewrites the live records into a new generation and retires the old ones
+    /// to `N.changes` files, which only [`KvStore::changes`] reads.
+    ///
+    /// This runs on its own once enough stale bytes pile up. Run it after changing
+    /// the encryption key to re-encrypt every live record with the new key; the
+    /// retired generations keep the old one until they are removed.
+    pub fn compact(&mut self) -> Result<()> {
+        if self.writer.is_none() {
+            return Err(Error::ReadOnly);
+        }
+        let started = Instant::now();
+        let compaction_gen = self.current_gen + 1;
+        self.current_gen += 2;
+        info!(
+            gen = compaction_gen,
+            stale_bytes = self.compaction,
+            "starting compaction"
+        );
+        self.writer = Some(Self::new_log_file(
+            &self.path,
+            self.current_gen,
+            &mut self.readers,
+        )?);
+
+        let compacted_end = self.compacted_end(compaction_gen)?;
+        let mut compaction_writer =
+            Self::new_log_file(&self.path, compaction_gen, &mut self.readers)?;
+
+        let readers = &mut self.readers;
+        let keyring = &self.keyring;
+        let decode = !keyring.is_empty() || !self.blobs.is_empty();
+        let mut blobs = Vec::new();
+        let mut hints = HintWriter::create(&self.path, compaction_gen, keyring)?;
+        let mut new_pos = 0;
+        self.index.rebuild(
+            |key, cmd_pos| {
+                let reader = readers
+                    .get_mut(&cmd_pos.gen)
+                    .unwrap_or_else(|| panic!("Cannot find log reader: {}", cmd_pos.gen));
+                if reader.pos != cmd_pos.pos {
+                    reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+                }
+
+                let len = if !decode {
+                    let mut entry_reader = reader.take(cmd_pos.len);
+                    std::io::copy(&mut entry_reader, &mut compaction_writer)?
+                } else {
+                    let mut buf = vec![0; cmd_pos.len as usize];
+                    reader.read_exact(&mut buf)?;
+                    let cmd: Command = serde_json::from_slice(&buf)?;
+                    let key_id = match &cmd {
+                        Command::Sealed(sealed) => Some(sealed.key_id()),
+                        _ => None,
+                    };
+                    let cmd = cmd.unseal(keyring, cmd_pos.gen)?;
+                    if let Command::SetBlob { blob, .. } = &cmd {
+                        blobs.push((key.to_owned(), blob.clone()));
+                    }
+                    if key_id.is_some() || keyring.current().is_some() {
+                        // Sealed records are bound to their generation, and may
+                        // be sealed with an old key or need to be sealed at all
+                        buf = serde_json::to_vec(&cmd.seal(keyring, compaction_gen)?)?;
+                    }
+                    compaction_writer.write_all(&buf)?;
+                    buf.len() as u64
+                };
+                let new_cmd_pos = (compaction_gen, new_pos..new_pos + len).into();
+                hints.append(key, new_cmd_pos)?;
+                new_pos += len;
+                Ok(new_cmd_pos)
+            },
+            Checkpoint::at(self.current_gen, 0, 0),
+        )?;
+        compaction_writer.flush()?;
+        if let Some(maps) = &mut self.maps {
+            if let Some(map) = map_log(&self.path, compaction_gen)? {
+                maps.insert(compaction_gen, map);
+            }
+        }
+        hints.finish()?;
+        self.index.commit()?;
+        changes::write_marker(&self.path, compaction_gen, compacted_end)?;
+
+        let stale_gens: Vec<_> = self
+            .readers
+            .keys()
+            .filter(|&&gen| gen < compaction_gen)
+            .cloned()
+            .collect();
+        for &stale_gen in &stale_gens {
+            self.readers.remove(&stale_gen);
+            if let Some(maps) = &mut self.maps {
+                maps.remove(&stale_gen);
+            }
+            changes::retire(&self.path, stale_gen)?;
+            hint::remove(&self.path, stale_gen)?;
+            debug!(gen = stale_gen, "retired stale generation");
+        }
+        if let Some(&oldest) = stale_gens.iter().min() {
+            changes::trim_history(&self.path, oldest, self.change_retention)?;
+        }
+        self.compaction = 0;
+        self.collect_blobs(blobs)?;
+        let elapsed = started.elapsed();
+        self.compactions.count += 1;
+        self.compactions.last = Some(elapsed);
+        self.compactions.save(&self.path)?;
+        info!(
+            gen = compaction_gen,
+            keys = self.index.len(),
+            bytes = new_pos,
+            duration = ?elapsed,
+            "finished compaction"
+        );
+
+        Ok(())
+    }
+
+    /// Sets the keys of blobs kept in files that are mostly garbage, or sealed
+    /// with an old key, again so that their values move to the current blob
+    /// file, then removes the files no live record points to.
+    fn collect_blobs(&mut self, live: Vec<(String, BlobRef)>) -> Result<()> {
+        let mut live_bytes: HashMap<u64, u64> = HashMap::new();
+        for (_, blob) in &live {
+            *live_bytes.entry(blob.file).or_default() += blob.len;
+        }
+        // Files are shared by every process writing to the store, so the active
+        // one is ended once it is mostly garbage for it to be collected too
+        if let Some(file) = self.blobs.active_file() {
+            let live = live_bytes.get(&file).cloned().unwrap_or(0);
+            if live * 2 < self.blobs.file_len(file)? {
+                self.blobs.start_file()?;
+            }
+        }
+        let files: HashSet<u64> = self.blobs.immutable_files().into_iter().collect();
+        let mut sparse = HashSet::new();
+        for &file in &files {
+            let live = live_bytes.get(&file).cloned().unwrap_or(0);
+            if 0 < live && live * 2 < self.blobs.file_len(file)? {
+                sparse.insert(file);
+            }
+        }
+
+        for (key, blob) in live {
+            let old_key = !self.keyring.is_empty() && blob.key_id != self.keyring.current();
+            if !files.contains(&blob.file) || !(sparse.contains(&blob.file) || old_key) {
+                continue;
+            }
+            let value = self.blobs.read(&blob, &self.keyring)?;
+            let moved = self.blobs.write(&value, self.compression, &self.keyring)?;
+            let command = Command::SetBlob {
+                key: key.clone(),
+                blob: moved,
+            };
+            self.append(key, &command.seal(&self.keyring, self.current_gen)?)?;
+            *live_bytes.get_mut(&blob.file).unwrap() -= blob.len;
+        }
+
+        for file in files {
+            if live_bytes.get(&file).cloned().unwrap_or(0) == 0 {
+                self.blobs.remove(file)?;
+                debug!(file, "removed blob file");
+            }
+        }
+        self.blobs.reset_garbage()?;
+        Ok(())
+    }
+
+    /// Appends a record setting `key` to the current generation.
+    fn append(&mut self, key: String, command: &Command) -> Result<()> {
+        let writer = self.writer.as_mut().ok_or(Error::ReadOnly)?;
+        let pos = writer.pos;
+        serde_json::to_writer(&mut *writer, command)?;
+        writer.flush()?;
+        let end = writer.pos;
+
+        if let Some(cache) = &mut self.cache {
+            cache.remove(&key);
+        }
+        if let Some(old_cmd) = self
+            .index
+            .insert(key, (self.current_gen, pos..end).into())?
+        {
+            self.discard(old_cmd)?;
+        }
+        self.index
+            .checkpoint(Checkpoint::at(self.current_gen, end, self.compaction))?;
+        Ok(())
+    }
+
+    /// Accounts for a record which is no longer live.
+    fn discard(&mut self, cmd_pos: Pos) -> Result<()> {
+        self.compaction += cmd_pos.len;
+        if !self.blobs.is_empty() {
+            if let Command::SetBlob { blob, .. } = self.read_command(cmd_pos)? {
+                self.blobs.add_garbage(blob.len)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_command(&mut self, cmd_pos: Pos) -> Result<Command> {
+        read_command(
+            &mut self.readers,
+            self.maps.as_ref(),
+            &self.keyring,
+            cmd_pos,
+        )
+    }
+
+    fn load_gens_list(path: &Path) -> Result<Vec<u64>> {
+        let mut list: Vec<u64> = fs::read_dir(path)?
+            .flat_map(|res| -> Result<_> { Ok(res?.path()) })
+            .filter(|path| path.is_file() && path.extension() == Some("log".as_ref()))
+            .flat_map(|path| {
+                path.file_name()
+                    .and_then(OsStr::to_str)
+                    .map(|s| s.trim_end_matches(".log"))
+                    .map(str::parse::<u64>)
+            })
+            .flatten()
+            .collect();
+
+        list.sort_unstable();
+        Ok(list)
+    }
+
+    fn new_log_file(
+        path: &Path,
+        gen: u64,
+        readers: &mut HashMap<u64, BufReaderWithPos<File>>,
+    ) -> Result<BufWriterWithPos<File>> {
+        let path = log_path(path, gen);
+
+        let writer =
+            BufWriterWithPos::new(OpenOptions::new().create(true).append(true).open(&path)?)?;
+        let reader = BufReaderWithPos::new(File::open(&path)?)?;
+        readers.insert(gen, reader);
+        debug!(path = %path.display(), "created log file");
+
+        Ok(writer)
+    }
+}
+
+impl KvsEngine for KvStore {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        if self.writer.is_none() {
+            return Err(Error::ReadOnly);
+        }
+        let watched = if self.watchers.is_watched(&key) {
+            Some((key.clone(), value.clone()))
+        } else {
+            None
+        };
+        let command = match self.blob_threshold {
+            Some(threshold) if value.len() >= threshold => Command::SetBlob {
+                key: key.clone(),
+                blob: self.blobs.write(&value, self.compression, &self.keyring)?,
+            },
+            _ => Command::set(key.clone(), value, self.compression)?,
+        };
+        self.append(key, &command.seal(&self.keyring, self.current_gen)?)?;
+        if let Some((key, value)) = watched {
+            self.watchers.notify(&key, Some(&value));
+        }
+
+        if self.compaction > COMPACTION_THRESHOLD || self.blobs.garbage() > BLOB_GARBAGE_THRESHOLD {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        if let Some(value) = self.cache.as_mut().and_then(|cache| cache.get(&key)) {
+            return Ok(Some(value));
+        }
+        if let Some(cmd_pos) = self.index.get(&key)? {
+            let cmd = self.read_command(cmd_pos)?;
+            let value = value_of(cmd, &mut self.blobs, &self.keyring)?;
+            if let Some(cache) = &mut self.cache {
+                cache.insert(key, value.clone());
+            }
+            Ok(Some(value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        let command = Command::Rm { key: key.clone() }.seal(&self.keyring, self.current_gen)?;
+        if let Some(cache) = &mut self.cache {
+            cache.remove(&key);
+        }
+
+        let writer = self.writer.as_mut().ok_or(Error::ReadOnly)?;
+        if self.index.get(&key)?.is_some() {
+            let pos = writer.pos;
+            serde_json::to_writer(&mut *writer, &command)?;
+            writer.flush()?;
+            let end = writer.pos;
+
+            if let Some(old_cmd) = self.index.remove(&key)? {
+                self.discard(old_cmd)?;
+            }
+            self.compaction += end - pos;
+            self.index
+                .checkpoint(Checkpoint::at(self.current_gen, end, self.compaction))?;
+            self.watchers.notify(&key, None);
+            Ok(())
+        } else {
+            Err(Error::KeyNotFound(key))
+        }
+    }
+
+    fn scan(&mut self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        let KvStore {
+            index,
+            readers,
+            maps,
+            blobs,
+            keyring,
+            ..
+        } = self;
+        index.scan(|key, cmd_pos| {
+            let cmd = read_command(readers, maps.as_ref(), keyring, cmd_pos)?;
+            f(key, &value_of(cmd, blobs, keyring)?)
+        })
+    }
+
+    /// A read-only store never sends events, changes made by the process
+    /// writing to the directory are not seen.
+    fn watch(&mut self, prefix: &str) -> Receiver<WatchEvent> {
+        self.watchers.subscribe(prefix)
+    }
+}
+
+fn log_path(path: &Path, gen: u64) -> PathBuf {
+    path.join(format!("{}.log", gen))
+}
+
+/// Reads and unseals the record at `cmd_pos`.
+fn read_command(
+    readers: &mut HashMap<u64, BufReaderWithPos<File>>,
+    maps: Option<&HashMap<u64, Mmap>>,
+    keyring: &Keyring,
+    cmd_pos: Pos,
+) -> Result<Command> {
+    let map = maps.and_then(|maps| maps.get(&cmd_pos.gen));
+    let cmd: Command = if let Some(map) = map {
+        let start = cmd_pos.pos as usize;
+        serde_json::from_slice(&map[start..start + cmd_pos.len as usize])?
+    } else {
+        let reader = readers
+            .get_mut(&cmd_pos.gen)
+            .unwrap_or_else(|| panic!("Can't find log file: {}.log", cmd_pos.gen));
+
+        reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+        serde_json::from_reader(reader.take(cmd_pos.len))?
+    };
+    cmd.unseal(keyring, cmd_pos.gen)
+}
+
+/// Returns the value set by a record read from the index.
+fn value_of(cmd: Command, blobs: &mut BlobStore, keyring: &Keyring) -> Result<String> {
+    match cmd {
+        Command::Set {
+            value, compression, ..
+        } => compression.decode(value),
+        Command::SetBlob { blob, .. } => blobs.read(&blob, keyring),
+        _ => Err(Error::UnexpectedCommandType),
+    }
+}
+
+/// Maps a generation that is no longer written to, `None` if it is empty.
+fn map_log(path: &Path, gen: u64) -> Result<Option<Mmap>> {
+    let file = File::open(log_path(path, gen))?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: log files are only appended to while they are the current
+    // generation and never modified in place once a newer one exists; repair
+    // and compaction replace or remove whole files, which leaves existing
+    // mappings intact.
+    Ok(Some(unsafe { Mmap::map(&file)? }))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct Pos {
+    gen: u64,
+    pos: u64,
+    len: u64,
+}
+
+impl From<(u64, Range<u64>)> for Pos {
+    fn from((gen, range): (u64, Range<u64>)) -> Self {
+        Self {
+            gen,
+            pos: range.start,
+            len: range.end - range.start,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BufWriterWithPos<T: Write + Seek> {
+    inner: BufWriter<T>,
+    pos: u64,
+}
+
+impl<T: Write + Seek> BufWriterWithPos<T> {
+    pub fn new(mut inner: T) -> Result<Self> {
+        let pos = inner.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            inner: BufWriter::new(inner),
+            pos,
+        })
+    }
+}
+
+impl<T: Write + Seek> Write for BufWriterWithPos<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.pos += len as u64;
+
+        Ok(len)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Write + Seek> Seek for BufWriterWithPos<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+#[derive(Debug)]
+struct BufReaderWithPos<T: Read + Seek> {
+    inner: BufReader<T>,
+    pos: u64,
+}
+
+impl<T: Read + Seek> BufReaderWithPos<T> {
+    pub fn new(mut inner: T) -> Result<Self> {
+        let pos = inner.stream_position()?;
+        Ok(Self {
+            inner: BufReader::new(inner),
+            pos,
+        })
+    }
+}
+
+impl<T: Read + Seek> Read for BufReaderWithPos<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<T: Read + Seek> Seek for BufReaderWithPos<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
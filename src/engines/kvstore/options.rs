@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use super::KvStore;
+use crate::{Compression, EncryptionKey, Result};
+
+/// Options for opening a [`KvStore`].
+///
+/// ```no_run
+/// # use kvs::{IndexMode, KvStoreOptions};
+/// let store = KvStoreOptions::new()
+///     .index_mode(IndexMode::OnDisk { cache_blocks: 1024 })
+///     .open("data")?;
+/// # Ok::<(), kvs::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct KvStoreOptions {
+    pub(super) read_only: bool,
+    pub(super) index_mode: IndexMode,
+    pub(super) compression: Compression,
+    pub(super) encryption_key: Option<EncryptionKey>,
+    pub(super) previous_keys: Vec<EncryptionKey>,
+    pub(super) value_cache: Option<usize>,
+    pub(super) mmap: bool,
+    pub(super) blob_threshold: Option<usize>,
+    pub(super) change_retention: u64,
+}
+
+/// Where the index of live keys is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexMode {
+    /// Every key is kept in memory.
+    #[default]
+    Memory,
+    /// Keys are kept in sorted `N.index` run files in the data directory, listed
+    /// by `kvs.index`. Only the first key of every block of 64 entries, up to
+    /// `cache_blocks` recently used blocks and the changes not yet written to a
+    /// run are kept in memory.
+    OnDisk { cache_blocks: usize },
+}
+
+impl KvStoreOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`KvStore::open_read_only`].
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn index_mode(mut self, index_mode: IndexMode) -> Self {
+        self.index_mode = index_mode;
+        self
+    }
+
+    /// Codec for values written from now on, records already in the log keep
+    /// theirs. Values which would not get smaller are stored as is.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Encrypts records and hint files written from now on with `key`.
+    ///
+    /// Encrypted data is bound to the file it was written to, so it cannot be
+    /// copied into another generation. A record can still be repeated within its
+    /// own generation, and dropping records from the end of the log or restoring
+    /// an older copy of the directory is not detected.
+    ///
+    /// Data written with other keys stays readable as long as they are passed to
+    /// [`KvStoreOptions::previous_key`]; [`KvStore::compact`] re-encrypts it with
+    /// this key. Cannot be combined with [`IndexMode::OnDisk`], whose file holds
+    /// plain keys.
+    pub fn encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Adds a key that is only used to decrypt data written before a rotation.
+    pub fn previous_key(mut self, key: EncryptionKey) -> Self {
+        self.previous_keys.push(key);
+        self
+    }
+
+    /// Keeps recently read values in memory, up to `bytes` of keys and values.
+    /// Hits and misses are reported by [`KvStore::stats`].
+    pub fn value_cache(mut self, bytes: usize) -> Self {
+        self.value_cache = Some(bytes);
+        self
+    }
+
+    /// Memory-maps generations which are no longer written to, so reads from them
+    /// decode records in place instead of seeking a buffered reader.
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Stores values of at least `bytes` in separate `N.blob` files, so that
+    /// compaction only copies the records pointing at them. Blob files which
+    /// are mostly garbage have their live values moved on compaction.
+    pub fn blob_threshold(mut self, bytes: usize) -> Self {
+        self.blob_threshold = Some(bytes);
+        self
+    }
+
+    /// Keeps up to `bytes` of generations replaced by compactions as
+    /// `N.changes` files, so that [`KvStore::changes`] consumers which fell
+    /// behind can still resume. The generations replaced by the latest
+    /// compaction are kept regardless.
+    pub fn change_retention(mut self, bytes: u64) -> Self {
+        self.change_retention = bytes;
+        self
+    }
+
+    pub fn open(&self, path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with(path.into(), self)
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::KvStore;
+use crate::Result;
+
+const STATS_FILE: &str = "kvs.stats";
+
+/// Snapshot of the state of an open [`KvStore`].
+#[derive(Debug)]
+pub struct Stats {
+    pub live_keys: usize,
+    pub generations: Vec<GenerationStats>,
+    /// Compactions run on the data directory, by this or earlier processes.
+    pub compactions: u64,
+    pub last_compaction: Option<Duration>,
+    /// Reads served by the value cache, zero without one.
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Bytes of blob files held by values overwritten or removed since the
+    /// blobs were last collected.
+    pub blob_garbage: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GenerationStats {
+    pub gen: u64,
+    pub total_bytes: u64,
+    pub stale_bytes: u64,
+}
+
+impl KvStore {
+    pub fn stats(&self) -> Result<Stats> {
+        let mut live_bytes: BTreeMap<u64, u64> = BTreeMap::new();
+        self.index.scan(|_, pos| {
+            *live_bytes.entry(pos.gen).or_default() += pos.len;
+            Ok(())
+        })?;
+
+        let mut gens: Vec<_> = self.readers.keys().cloned().collect();
+        gens.sort_unstable();
+
+        let mut generations = Vec::with_capacity(gens.len());
+        for gen in gens {
+            // The file may have been removed since by another process compacting
+            let total_bytes = self.readers[&gen].inner.get_ref().metadata()?.len();
+            let live_bytes = live_bytes.get(&gen).cloned().unwrap_or(0);
+            generations.push(GenerationStats {
+                gen,
+                total_bytes,
+                stale_bytes: total_bytes - live_bytes,
+            });
+        }
+
+        Ok(Stats {
+            live_keys: self.index.len(),
+            generations,
+            compactions: self.compactions.count,
+            last_compaction: self.compactions.last,
+            cache_hits: self.cache.as_ref().map_or(0, |cache| cache.hits),
+            cache_misses: self.cache.as_ref().map_or(0, |cache| cache.misses),
+            blob_garbage: self.blobs.garbage(),
+        })
+    }
+}
+
+/// Compaction history, kept in the data directory so that it outlives the
+/// process which compacted.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct CompactionStats {
+    pub(super) count: u64,
+    pub(super) last: Option<Duration>,
+}
+
+impl CompactionStats {
+    pub(super) fn load(path: &Path) -> Result<Self> {
+        match File::open(path.join(STATS_FILE)) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the stats file atomically.
+    pub(super) fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.join(format!("{}.tmp", STATS_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(tmp_path, path.join(STATS_FILE))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Deserializer;
+
+use super::{hint, index::DiskIndex, lock_dir, log_path, KvStore, Pos};
+use crate::command::Command;
+use crate::Result;
+
+/// Outcome of an offline consistency check of a data directory.
+///
+/// Keys of encrypted records are unknown without opening the store, and any of
+/// them may overwrite or remove a key of another generation. Once a directory
+/// holds encrypted records, stale bytes and live keys are therefore `None`.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub generations: Vec<GenerationReport>,
+    pub live_keys: Option<usize>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no generation contains unreadable bytes.
+    pub fn is_clean(&self) -> bool {
+        self.generations.iter().all(|gen| gen.corrupt.is_empty())
+    }
+}
+
+/// Consistency details of a single `N.log` file.
+#[derive(Debug, Default)]
+pub struct GenerationReport {
+    pub gen: u64,
+    pub records: u64,
+    /// Records among `records` which are encrypted.
+    pub sealed_records: u64,
+    pub total_bytes: u64,
+    pub stale_bytes: Option<u64>,
+    pub live_keys: Option<usize>,
+    /// Byte ranges which could not be decoded as records.
+    pub corrupt: Vec<Range<u64>>,
+}
+
+impl GenerationReport {
+    pub fn stale_ratio(&self) -> Option<f64> {
+        let stale_bytes = self.stale_bytes?;
+        Some(if self.total_bytes == 0 {
+            0.0
+        } else {
+            stale_bytes as f64 / self.total_bytes as f64
+        })
+    }
+
+    /// A generation is orphaned when none of its records is known to be live
+    /// anymore, which is never the case while live keys are unknown.
+    pub fn is_orphaned(&self) -> bool {
+        self.live_keys == Some(0)
+    }
+}
+
+impl KvStore {
+    /// Checks every generation in `path` without opening the store.
+    pub fn verify(path: impl Into<PathBuf>) -> Result<VerifyReport> {
+        check(&path.into(), false)
+    }
+
+    /// Like [`KvStore::verify`], but rewrites generations containing corrupt
+    /// bytes so that only their readable records are kept.
+    ///
+    /// The returned report describes the directory as it was found. Fails with
+    /// `Error::DirectoryLocked` while the store is open elsewhere.
+    pub fn repair(path: impl Into<PathBuf>) -> Result<VerifyReport> {
+        let path = path.into();
+        let _lock = lock_dir(&path)?;
+        check(&path, true)
+    }
+}
+
+pub(super) enum Segment {
+    Record(Range<u64>, Command),
+    Corrupt(Range<u64>),
+}
+
+/// Splits raw log contents into records, skipping over undecodable bytes up to
+/// the start of the next record.
+pub(super) fn scan(buf: &[u8]) -> Vec<Segment> {
+    segments(buf).collect()
+}
+
+/// Like [`scan`], decoding one segment at a time.
+pub(super) fn segments(buf: &[u8]) -> Segments<'_> {
+    Segments { buf, pos: 0 }
+}
+
+pub(super) struct Segments<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Iterator for Segments<'_> {
+    type Item = Segment;
+
+    fn next(&mut self) -> Option<Segment> {
+        let pos = self.pos;
+        let mut stream = Deserializer::from_slice(&self.buf[pos..]).into_iter::<Command>();
+        match stream.next()? {
+            Ok(cmd) => {
+                self.pos = pos + stream.byte_offset();
+                Some(Segment::Record(pos as u64..self.pos as u64, cmd))
+            }
+            Err(_) => {
+                self.pos = next_record_start(self.buf, pos + 1).unwrap_or(self.buf.len());
+                Some(Segment::Corrupt(pos as u64..self.pos as u64))
+            }
+        }
+    }
+}
+
+fn next_record_start(buf: &[u8], from: usize) -> Option<usize> {
+    const TAGS: [&[u8]; 4] = [b"{\"Set\"", b"{\"SetBlob\"", b"{\"Rm\"", b"{\"Sealed\""];
+
+    (from..buf.len()).find(|&i| TAGS.iter().any(|tag| buf[i..].starts_with(tag)))
+}
+
+fn check(path: &Path, repair: bool) -> Result<VerifyReport> {
+    let mut index: BTreeMap<String, Pos> = BTreeMap::new();
+    let mut reports = Vec::new();
+    let mut stale: HashMap<u64, u64> = HashMap::new();
+
+    for gen in KvStore::load_gens_list(path)? {
+        let buf = fs::read(log_path(path, gen))?;
+        let segments = scan(&buf);
+
+        let mut report = GenerationReport {
+            gen,
+            total_bytes: buf.len() as u64,
+            ..GenerationReport::default()
+        };
+        for segment in &segments {
+            match segment {
+                Segment::Record(range, cmd) => {
+                    report.records += 1;
+                    let old = match cmd {
+                        Command::Set { key, .. } | Command::SetBlob { key, .. } => {
+                            index.insert(key.clone(), (gen, range.clone()).into())
+                        }
+                        Command::Rm { key } => {
+                            *stale.entry(gen).or_default() += range.end - range.start;
+                            index.remove(key)
+                        }
+                        Command::Sealed(_) => {
+                            report.sealed_records += 1;
+                            None
+                        }
+                    };
+                    if let Some(old) = old {
+                        *stale.entry(old.gen).or_default() += old.len;
+                    }
+                }
+                Segment::Corrupt(range) => report.corrupt.push(range.clone()),
+            }
+        }
+
+        if repair && !report.corrupt.is_empty() {
+            salvage(path, gen, &buf, &segments)?;
+        }
+        reports.push(report);
+    }
+
+    if reports.iter().any(|report| report.sealed_records > 0) {
+        return Ok(VerifyReport {
+            generations: reports,
+            live_keys: None,
+        });
+    }
+    for report in &mut reports {
+        report.stale_bytes = Some(stale.get(&report.gen).cloned().unwrap_or(0));
+        report.live_keys = Some(index.values().filter(|pos| pos.gen == report.gen).count());
+    }
+
+    Ok(VerifyReport {
+        generations: reports,
+        live_keys: Some(index.len()),
+    })
+}
+
+fn salvage(path: &Path, gen: u64, buf: &[u8], segments: &[Segment]) -> Result<()> {
+    let tmp_path = path.join(format!("{}.log.repair", gen));
+    let mut tmp = File::create(&tmp_path)?;
+    for segment in segments {
+        if let Segment::Record(range, _) = segment {
+            tmp.write_all(&buf[range.start as usize..range.end as usize])?;
+        }
+    }
+    tmp.sync_all()?;
+    fs::rename(tmp_path, log_path(path, gen))?;
+
+    // Record offsets changed, so positions derived from the old file are stale
+    hint::remove(path, gen)?;
+    DiskIndex::remove_files(path)?;
+
+    Ok(())
+}
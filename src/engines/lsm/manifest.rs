@@ -0,0 +1,39 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+const MANIFEST_FILE: &str = "MANIFEST";
+
+/// The tables making up each level, the only record of which tables are live.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct Manifest {
+    pub next_id: u64,
+    pub levels: Vec<Vec<u64>>,
+}
+
+impl Manifest {
+    pub(super) fn load(dir: &Path) -> Result<Self> {
+        match File::open(dir.join(MANIFEST_FILE)) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the manifest in `dir` atomically.
+    pub(super) fn save(&self, dir: &Path) -> Result<()> {
+        let tmp_path = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(tmp_path, dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
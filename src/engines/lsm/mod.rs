@@ -0,0 +1,416 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Write},
+    iter::Peekable,
+    mem,
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+};
+
+use serde_json::Deserializer;
+use tracing::{debug, info};
+
+use super::{lock_dir, WatchEvent, Watchers};
+use crate::{command::Command, Compression, Error, KvsEngine, Result};
+
+mod manifest;
+mod sstable;
+
+use manifest::Manifest;
+use sstable::{table_path, Entry, Table, TableWriter};
+
+const WAL_FILE: &str = "lsm.wal";
+const MEMTABLE_LIMIT: u64 = 1024 * 1024;
+const L0_LIMIT: usize = 4;
+const LEVEL_BASE_SIZE: u64 = 10 * 1024 * 1024;
+const LEVEL_SIZE_MULTIPLIER: u64 = 10;
+const TABLE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Log-structured merge-tree engine.
+///
+/// Writes go to a write-ahead log and an in-memory table, which is flushed to a
+/// sorted table file in level 0 once it grows past 1 MiB. Level 0 tables may
+/// overlap, once there are four of them they are merged into level 1. Every
+/// deeper level is a run of non-overlapping tables ten times larger than the
+/// previous one, overflowing into the next level one table at a time.
+#[derive(Debug)]
+pub struct LsmStore {
+    path: PathBuf,
+    /// Latest changes, `None` marking a removed key.
+    memtable: BTreeMap<String, Option<String>>,
+    memtable_size: u64,
+    wal: BufWriter<File>,
+    levels: Vec<Vec<Table>>,
+    next_id: u64,
+    watchers: Watchers,
+    // Held for the lifetime of the store, the lock is released on drop.
+    _lock: File,
+}
+
+impl LsmStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+        let lock = lock_dir(&path)?;
+
+        let manifest = Manifest::load(&path)?;
+        let mut levels = manifest
+            .levels
+            .iter()
+            .map(|ids| ids.iter().map(|&id| Table::open(&path, id)).collect())
+            .collect::<Result<Vec<Vec<_>>>>()?;
+        if levels.is_empty() {
+            levels.push(Vec::new());
+        }
+        remove_unused_tables(&path, &levels)?;
+
+        let (memtable, memtable_size) = replay_wal(&path)?;
+        let wal = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path.join(WAL_FILE))?,
+        );
+
+        info!(
+            path = %path.display(),
+            tables = levels.iter().map(Vec::len).sum::<usize>(),
+            levels = levels.len(),
+            memtable_keys = memtable.len(),
+            "opened lsm store"
+        );
+
+        Ok(LsmStore {
+            path,
+            memtable,
+            memtable_size,
+            wal,
+            levels,
+            next_id: manifest.next_id,
+            watchers: Watchers::default(),
+            _lock: lock,
+        })
+    }
+
+    fn write(&mut self, command: Command) -> Result<()> {
+        serde_json::to_writer(&mut self.wal, &command)?;
+        self.wal.flush()?;
+
+        let (key, value) = match command {
+            Command::Set {
+                key,
+                value,
+                compression,
+            } => (key, Some(compression.decode(value)?)),
+            Command::Rm { key } => (key, None),
+            Command::SetBlob { .. } | Command::Sealed(_) => {
+                return Err(Error::UnexpectedCommandType)
+            }
+        };
+        self.watchers.notify(&key, value.as_deref());
+        self.memtable_size += entry_size(&key, &value);
+        self.memtable.insert(key, value);
+
+        if self.memtable_size > MEMTABLE_LIMIT {
+            self.flush_memtable()?;
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the memtable into a new level 0 table and starts a new log.
+    fn flush_memtable(&mut self) -> Result<()> {
+        let mut writer = TableWriter::create(&self.path, self.next_id)?;
+        self.next_id += 1;
+        for (key, value) in mem::take(&mut self.memtable) {
+            writer.add(key, value)?;
+        }
+        let table = writer.finish()?;
+        debug!(id = table.id, bytes = table.size, "flushed memtable");
+        self.levels[0].push(table);
+        self.save_manifest()?;
+
+        self.wal = BufWriter::new(File::create(self.path.join(WAL_FILE))?);
+        self.memtable_size = 0;
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        loop {
+            if self.levels[0].len() >= L0_LIMIT {
+                self.compact_level(0)?;
+                continue;
+            }
+            let overflowing = (1..self.levels.len()).find(|&level| {
+                let size: u64 = self.levels[level].iter().map(|table| table.size).sum();
+                size > LEVEL_BASE_SIZE * LEVEL_SIZE_MULTIPLIER.pow(level as u32 - 1)
+            });
+            match overflowing {
+                Some(level) => self.compact_level(level)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Merges tables of `level` with the overlapping tables of the next level.
+    fn compact_level(&mut self, level: usize) -> Result<()> {
+        if self.levels.len() <= level + 1 {
+            self.levels.push(Vec::new());
+        }
+
+        // Ordered by precedence, newest first
+        let mut inputs: Vec<Table> = if level == 0 {
+            self.levels[0].drain(..).rev().collect()
+        } else {
+            vec![self.levels[level].remove(0)]
+        };
+        let first_key = inputs
+            .iter()
+            .map(Table::first_key)
+            .min()
+            .unwrap()
+            .to_owned();
+        let last_key = inputs.iter().map(Table::last_key).max().unwrap().to_owned();
+        let (overlapping, rest) = mem::take(&mut self.levels[level + 1])
+            .into_iter()
+            .partition(|table| table.overlaps(&first_key, &last_key));
+        self.levels[level + 1] = rest;
+        inputs.extend::<Vec<_>>(overlapping);
+
+        // Nothing older is left for a removed key to shadow
+        let bottom = self.levels[level + 2..].iter().all(Vec::is_empty);
+        let sources = inputs
+            .iter()
+            .map(|table| table.iter().map(boxed))
+            .collect::<Result<_>>()?;
+
+        let mut outputs = Vec::new();
+        let mut writer: Option<TableWriter> = None;
+        for entry in Merge::new(sources) {
+            let (key, value) = entry?;
+            if bottom && value.is_none() {
+                continue;
+            }
+            if writer.is_none() {
+                writer = Some(TableWriter::create(&self.path, self.next_id)?);
+                self.next_id += 1;
+            }
+            let table_writer = writer.as_mut().unwrap();
+            table_writer.add(key, value)?;
+            if table_writer.size() >= TABLE_SIZE {
+                outputs.push(writer.take().unwrap().finish()?);
+            }
+        }
+        if let Some(writer) = writer.filter(|writer| !writer.is_empty()) {
+            outputs.push(writer.finish()?);
+        }
+
+        info!(
+            level,
+            inputs = inputs.len(),
+            outputs = outputs.len(),
+            "compacted level"
+        );
+        let next_level = &mut self.levels[level + 1];
+        next_level.extend(outputs);
+        next_level.sort_by(|a, b| a.first_key().cmp(b.first_key()));
+        self.save_manifest()?;
+
+        for table in inputs {
+            fs::remove_file(table_path(&self.path, table.id))?;
+        }
+        Ok(())
+    }
+
+    fn save_manifest(&self) -> Result<()> {
+        Manifest {
+            next_id: self.next_id,
+            levels: self
+                .levels
+                .iter()
+                .map(|tables| tables.iter().map(|table| table.id).collect())
+                .collect(),
+        }
+        .save(&self.path)
+    }
+
+    /// Iterates over the memtable and every table merged, in key order.
+    fn entries(&self) -> Result<Merge> {
+        let mut sources = vec![boxed(
+            self.memtable
+                .clone()
+                .into_iter()
+                .map(Ok)
+                .collect::<Vec<_>>()
+                .into_iter(),
+        )];
+        for table in self.levels[0].iter().rev() {
+            sources.push(boxed(table.iter()?));
+        }
+        for table in self.levels.iter().skip(1).flatten() {
+            sources.push(boxed(table.iter()?));
+        }
+        Ok(Merge::new(sources))
+    }
+}
+
+impl KvsEngine for LsmStore {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.write(Command::set(key, value, Compression::None)?)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        if let Some(value) = self.memtable.get(&key) {
+            return Ok(value.clone());
+        }
+        for table in self.levels[0].iter_mut().rev() {
+            if let Some(value) = table.get(&key)? {
+                return Ok(value);
+            }
+        }
+        for tables in self.levels.iter_mut().skip(1) {
+            let candidate = tables.partition_point(|table| table.last_key() < key.as_str());
+            if let Some(table) = tables.get_mut(candidate) {
+                if let Some(value) = table.get(&key)? {
+                    return Ok(value);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        if self.get(key.clone())?.is_none() {
+            return Err(Error::KeyNotFound(key));
+        }
+        self.write(Command::Rm { key })
+    }
+
+    fn scan(&mut self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        for entry in self.entries()? {
+            if let (key, Some(value)) = entry? {
+                f(&key, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn watch(&mut self, prefix: &str) -> Receiver<WatchEvent> {
+        self.watchers.subscribe(prefix)
+    }
+}
+
+fn entry_size(key: &str, value: &Option<String>) -> u64 {
+    (key.len() + value.as_ref().map_or(0, String::len)) as u64
+}
+
+/// Rebuilds the memtable from the write-ahead log, cutting off a record torn by a crash.
+fn replay_wal(path: &Path) -> Result<(BTreeMap<String, Option<String>>, u64)> {
+    let mut memtable = BTreeMap::new();
+    let mut size = 0;
+    let wal_path = path.join(WAL_FILE);
+    if !wal_path.exists() {
+        return Ok((memtable, size));
+    }
+
+    let mut stream =
+        Deserializer::from_reader(BufReader::new(File::open(&wal_path)?)).into_iter::<Command>();
+    let mut valid_len = 0;
+    while let Some(cmd) = stream.next() {
+        let (key, value) = match cmd {
+            Err(err) if err.is_eof() => {
+                OpenOptions::new()
+                    .write(true)
+                    .open(&wal_path)?
+                    .set_len(valid_len as u64)?;
+                break;
+            }
+            Ok(Command::Set {
+                key,
+                value,
+                compression,
+            }) => (key, Some(compression.decode(value)?)),
+            Ok(Command::Rm { key }) => (key, None),
+            Ok(Command::SetBlob { .. }) | Ok(Command::Sealed(_)) => {
+                return Err(Error::UnexpectedCommandType)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        size += entry_size(&key, &value);
+        memtable.insert(key, value);
+        valid_len = stream.byte_offset();
+    }
+
+    Ok((memtable, size))
+}
+
+/// Removes tables left behind by an interrupted flush or compaction.
+fn remove_unused_tables(path: &Path, levels: &[Vec<Table>]) -> Result<()> {
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+        if file.extension() != Some("sst".as_ref()) {
+            continue;
+        }
+        let id = file
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .and_then(|stem| stem.parse::<u64>().ok());
+        let used = levels.iter().flatten().any(|table| Some(table.id) == id);
+        if !used {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+type Source = Box<dyn Iterator<Item = Result<Entry>>>;
+
+fn boxed(iter: impl Iterator<Item = Result<Entry>> + 'static) -> Source {
+    Box::new(iter)
+}
+
+/// Merges sorted sources, ordered by precedence, into one sorted stream where
+/// each key appears once with the value of the first source having it.
+struct Merge {
+    sources: Vec<Peekable<Source>>,
+}
+
+impl Merge {
+    fn new(sources: Vec<Source>) -> Self {
+        Merge {
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+        }
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut next: Option<(usize, String)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Err(_)) => return source.next(),
+                Some(Ok((key, _))) if next.as_ref().is_none_or(|(_, next_key)| key < next_key) => {
+                    next = Some((i, key.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        let (i, key) = next?;
+        let entry = self.sources[i].next();
+        for source in &mut self.sources {
+            while let Some(Ok((other_key, _))) = source.peek() {
+                if *other_key != key {
+                    break;
+                }
+                source.next();
+            }
+        }
+        entry
+    }
+}
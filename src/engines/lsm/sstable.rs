@@ -0,0 +1,205 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Deserializer;
+
+use crate::Result;
+
+/// Target size of a data block, entries are never split across blocks.
+const BLOCK_SIZE: usize = 4 * 1024;
+
+/// A key with its value, or `None` for a removed key.
+pub(super) type Entry = (String, Option<String>);
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Record {
+    key: String,
+    value: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BlockHandle {
+    first_key: String,
+    offset: u64,
+    len: u64,
+}
+
+/// Block index stored at the end of a table, followed by its offset as 8 bytes.
+#[derive(Serialize, Deserialize, Debug)]
+struct Footer {
+    blocks: Vec<BlockHandle>,
+    last_key: String,
+}
+
+pub(super) fn table_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.sst", id))
+}
+
+/// An immutable sorted file of entries.
+#[derive(Debug)]
+pub(super) struct Table {
+    pub id: u64,
+    pub size: u64,
+    path: PathBuf,
+    blocks: Vec<BlockHandle>,
+    last_key: String,
+    data_len: u64,
+    file: BufReader<File>,
+}
+
+impl Table {
+    pub(super) fn open(dir: &Path, id: u64) -> Result<Self> {
+        let path = table_path(dir, id);
+        let mut file = BufReader::new(File::open(&path)?);
+        let size = file.seek(SeekFrom::End(0))?;
+
+        let mut footer_offset = [0; 8];
+        file.seek(SeekFrom::End(-8))?;
+        file.read_exact(&mut footer_offset)?;
+        let data_len = u64::from_le_bytes(footer_offset);
+        file.seek(SeekFrom::Start(data_len))?;
+        let footer: Footer = serde_json::from_reader((&mut file).take(size - 8 - data_len))?;
+
+        Ok(Table {
+            id,
+            size,
+            path,
+            blocks: footer.blocks,
+            last_key: footer.last_key,
+            data_len,
+            file,
+        })
+    }
+
+    pub(super) fn first_key(&self) -> &str {
+        &self.blocks[0].first_key
+    }
+
+    pub(super) fn last_key(&self) -> &str {
+        &self.last_key
+    }
+
+    pub(super) fn overlaps(&self, first_key: &str, last_key: &str) -> bool {
+        self.first_key() <= last_key && first_key <= self.last_key()
+    }
+
+    /// Returns the entry of `key`, if this table has one.
+    pub(super) fn get(&mut self, key: &str) -> Result<Option<Option<String>>> {
+        if key > self.last_key() {
+            return Ok(None);
+        }
+        let block = match self
+            .blocks
+            .binary_search_by(|block| block.first_key.as_str().cmp(key))
+        {
+            Ok(block) => block,
+            Err(0) => return Ok(None),
+            Err(block) => block - 1,
+        };
+
+        let handle = &self.blocks[block];
+        self.file.seek(SeekFrom::Start(handle.offset))?;
+        for record in Deserializer::from_reader((&mut self.file).take(handle.len)).into_iter() {
+            let record: Record = record?;
+            if record.key == key {
+                return Ok(Some(record.value));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Iterates over all entries in key order.
+    pub(super) fn iter(&self) -> Result<impl Iterator<Item = Result<Entry>>> {
+        let file = BufReader::new(File::open(&self.path)?).take(self.data_len);
+        Ok(Deserializer::from_reader(file)
+            .into_iter::<Record>()
+            .map(|record| {
+                let record = record?;
+                Ok((record.key, record.value))
+            }))
+    }
+}
+
+/// Writes entries, added in key order, into a new table.
+pub(super) struct TableWriter {
+    dir: PathBuf,
+    id: u64,
+    writer: BufWriter<File>,
+    blocks: Vec<BlockHandle>,
+    block: Vec<u8>,
+    block_first_key: Option<String>,
+    last_key: String,
+    offset: u64,
+}
+
+impl TableWriter {
+    pub(super) fn create(dir: &Path, id: u64) -> Result<Self> {
+        Ok(TableWriter {
+            dir: dir.to_owned(),
+            id,
+            writer: BufWriter::new(File::create(table_path(dir, id))?),
+            blocks: Vec::new(),
+            block: Vec::new(),
+            block_first_key: None,
+            last_key: String::new(),
+            offset: 0,
+        })
+    }
+
+    pub(super) fn add(&mut self, key: String, value: Option<String>) -> Result<()> {
+        if self.block_first_key.is_none() {
+            self.block_first_key = Some(key.clone());
+        }
+        let record = Record { key, value };
+        serde_json::to_writer(&mut self.block, &record)?;
+        self.block.push(b'\n');
+        self.last_key = record.key;
+
+        if self.block.len() >= BLOCK_SIZE {
+            self.finish_block()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes written so far.
+    pub(super) fn size(&self) -> u64 {
+        self.offset + self.block.len() as u64
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    pub(super) fn finish(mut self) -> Result<Table> {
+        self.finish_block()?;
+        let footer = Footer {
+            blocks: self.blocks,
+            last_key: self.last_key,
+        };
+        serde_json::to_writer(&mut self.writer, &footer)?;
+        self.writer.write_all(&self.offset.to_le_bytes())?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+
+        Table::open(&self.dir, self.id)
+    }
+
+    fn finish_block(&mut self) -> Result<()> {
+        if let Some(first_key) = self.block_first_key.take() {
+            self.writer.write_all(&self.block)?;
+            self.blocks.push(BlockHandle {
+                first_key,
+                offset: self.offset,
+                len: self.block.len() as u64,
+            });
+            self.offset += self.block.len() as u64;
+            self.block.clear();
+        }
+        Ok(())
+    }
+}
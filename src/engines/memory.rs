@@ -0,0 +1,107 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+    sync::mpsc::Receiver,
+};
+
+use tracing::error;
+
+use super::{KvsEngine, WatchEvent, Watchers};
+use crate::{Error, Result};
+
+/// Engine keeping every key in memory.
+///
+/// Without a snapshot file nothing touches the filesystem. With one, the keys
+/// are loaded from it on open and written back by [`MemoryEngine::snapshot`], or
+/// on drop if anything changed since.
+#[derive(Debug, Default)]
+pub struct MemoryEngine {
+    map: BTreeMap<String, String>,
+    snapshot: Option<PathBuf>,
+    dirty: bool,
+    watchers: Watchers,
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens an engine persisted to the snapshot file at `path`, which need not exist yet.
+    pub fn with_snapshot(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let map = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(MemoryEngine {
+            map,
+            snapshot: Some(path),
+            dirty: false,
+            watchers: Watchers::default(),
+        })
+    }
+
+    /// Replaces the snapshot file atomically, does nothing without one.
+    pub fn snapshot(&mut self) -> Result<()> {
+        let path = match &self.snapshot {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &self.map)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(tmp_path, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl KvsEngine for MemoryEngine {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.watchers.notify(&key, Some(&value));
+        self.map.insert(key, value);
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        Ok(self.map.get(&key).cloned())
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        match self.map.remove(&key) {
+            Some(_) => {
+                self.watchers.notify(&key, None);
+                self.dirty = true;
+                Ok(())
+            }
+            None => Err(Error::KeyNotFound(key)),
+        }
+    }
+
+    fn scan(&mut self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        self.map.iter().try_for_each(|(key, value)| f(key, value))
+    }
+
+    fn watch(&mut self, prefix: &str) -> Receiver<WatchEvent> {
+        self.watchers.subscribe(prefix)
+    }
+}
+
+impl Drop for MemoryEngine {
+    fn drop(&mut self) {
+        if self.dirty {
+            if let Err(err) = self.snapshot() {
+                error!(%err, "failed to write snapshot");
+            }
+        }
+    }
+}
@@ -1,11 +1,85 @@
-use crate::Result;
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::mpsc::Receiver,
+};
+
+use fs2::FileExt;
+
+use crate::{Error, Result};
+
+const LOCK_FILE: &str = "kvs.lock";
 
 mod kvstore;
+mod lsm;
+mod memory;
+mod watch;
 
-pub use kvstore::KvStore;
+pub use kvstore::{
+    Change, EntryKind, GenerationReport, GenerationStats, IndexMode, KvStore, KvStoreOptions,
+    LogEntry, Stats, VerifyReport,
+};
+pub use lsm::LsmStore;
+pub use memory::MemoryEngine;
+pub use watch::WatchEvent;
+pub(crate) use watch::Watchers;
 
 pub trait KvsEngine {
     fn set(&mut self, key: String, value: String) -> Result<()>;
     fn get(&mut self, key: String) -> Result<Option<String>>;
     fn remove(&mut self, key: String) -> Result<()>;
+    /// Calls `f` with every live key and its value, in key order.
+    fn scan(&mut self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()>;
+    /// Returns every live key, in key order. This holds them all in memory,
+    /// prefer [`KvsEngine::scan`] over large stores.
+    fn keys(&mut self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        self.scan(&mut |key, _| {
+            keys.push(key.to_owned());
+            Ok(())
+        })?;
+        Ok(keys)
+    }
+    /// Subscribes to changes made through this engine to keys starting with
+    /// `prefix`, each sent once it has been written.
+    fn watch(&mut self, prefix: &str) -> Receiver<WatchEvent>;
+}
+
+impl<E: KvsEngine + ?Sized> KvsEngine for Box<E> {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        (**self).set(key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        (**self).get(key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        (**self).remove(key)
+    }
+
+    fn scan(&mut self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        (**self).scan(f)
+    }
+
+    fn watch(&mut self, prefix: &str) -> Receiver<WatchEvent> {
+        (**self).watch(prefix)
+    }
+}
+
+/// Takes an exclusive advisory lock on the data directory, so that only one
+/// process writes to it.
+fn lock_dir(path: &Path) -> Result<File> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.join(LOCK_FILE))?;
+    match lock.try_lock_exclusive() {
+        Ok(()) => Ok(lock),
+        Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+            Err(Error::DirectoryLocked(path.to_owned()))
+        }
+        Err(err) => Err(err.into()),
+    }
 }
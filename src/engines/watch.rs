@@ -0,0 +1,48 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// A change made through the engine a watcher subscribed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    Set { key: String, value: String },
+    Removed { key: String },
+}
+
+/// Subscribers to changes of keys starting with a prefix.
+#[derive(Debug, Default)]
+pub(crate) struct Watchers {
+    subscribers: Vec<(String, Sender<WatchEvent>)>,
+}
+
+impl Watchers {
+    pub(crate) fn subscribe(&mut self, prefix: &str) -> Receiver<WatchEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push((prefix.to_owned(), sender));
+        receiver
+    }
+
+    pub(crate) fn is_watched(&self, key: &str) -> bool {
+        self.subscribers
+            .iter()
+            .any(|(prefix, _)| key.starts_with(prefix.as_str()))
+    }
+
+    /// Sends the change of `key` to every matching subscriber, `None` meaning it
+    /// was removed. Subscribers whose receiver was dropped are forgotten.
+    pub(crate) fn notify(&mut self, key: &str, value: Option<&str>) {
+        self.subscribers.retain(|(prefix, sender)| {
+            if !key.starts_with(prefix.as_str()) {
+                return true;
+            }
+            let event = match value {
+                Some(value) => WatchEvent::Set {
+                    key: key.to_owned(),
+                    value: value.to_owned(),
+                },
+                None => WatchEvent::Removed {
+                    key: key.to_owned(),
+                },
+            };
+            sender.send(event).is_ok()
+        });
+    }
+}
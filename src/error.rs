@@ -1,5 +1,9 @@
+// `failure_derive` expands into impls nested in an anonymous const.
+#![allow(non_local_definitions)]
+
 use failure::Fail;
 use std::io;
+use std::path::PathBuf;
 
 #[derive(Fail, Debug)]
 pub enum Error {
@@ -11,6 +15,25 @@ pub enum Error {
     KeyNotFound(String),
     #[fail(display = "Unexpected command")]
     UnexpectedCommandType,
+    #[fail(display = "Data directory is used by another process: {:?}", _0)]
+    DirectoryLocked(PathBuf),
+    #[fail(display = "Store is opened read-only")]
+    ReadOnly,
+    #[fail(display = "Unable to decompress value: {}", _0)]
+    CorruptValue(String),
+    #[fail(display = "No encryption key with id {}", _0)]
+    MissingKey(u32),
+    #[fail(display = "Unable to decrypt record")]
+    Decryption,
+    #[fail(display = "The on-disk index cannot be used with encryption")]
+    EncryptedDiskIndex,
+    #[fail(display = "Changes from sequence number {} were compacted away", _0)]
+    Compacted(u64),
+    #[fail(
+        display = "Generation {} offset {} is out of the range of change sequence numbers",
+        _0, _1
+    )]
+    SequenceOverflow(u64, u64),
 }
 
 impl From<io::Error> for Error {
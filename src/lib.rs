@@ -1,8 +1,17 @@
 mod command;
+mod compression;
+mod encryption;
 mod engines;
 mod error;
+mod jsonl;
 
-pub use crate::engines::{KvStore, KvsEngine};
+pub use crate::compression::Compression;
+pub use crate::encryption::EncryptionKey;
+pub use crate::engines::{
+    Change, EntryKind, GenerationReport, GenerationStats, IndexMode, KvStore, KvStoreOptions,
+    KvsEngine, LogEntry, LsmStore, MemoryEngine, Stats, VerifyReport, WatchEvent,
+};
 pub use crate::error::Error;
+pub use crate::jsonl::{export, export_encrypted, import, import_encrypted};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,135 @@
+use kvs::{EncryptionKey, EntryKind, KvStore, KvStoreOptions, KvsEngine, LogEntry, Result};
+use std::fs;
+use tempfile::TempDir;
+
+const SET_KEY1: &str = r#"{"Set":{"key":"key1","value":"value1"}}"#;
+const SET_KEY2: &str = r#"{"Set":{"key":"key2","value":"value2"}}"#;
+const RM_KEY1: &str = r#"{"Rm":{"key":"key1"}}"#;
+
+#[test]
+fn verify_clean_directory() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(temp_dir.path().join("1.log"), [SET_KEY1, SET_KEY2].concat())?;
+    fs::write(temp_dir.path().join("2.log"), RM_KEY1)?;
+
+    let report = KvStore::verify(temp_dir.path())?;
+    assert!(report.is_clean());
+    assert_eq!(report.live_keys, Some(1));
+    assert_eq!(report.generations.len(), 2);
+
+    let gen1 = &report.generations[0];
+    assert_eq!(gen1.records, 2);
+    assert_eq!(gen1.live_keys, Some(1));
+    assert_eq!(gen1.stale_bytes, Some(SET_KEY1.len() as u64));
+    assert!(!gen1.is_orphaned());
+
+    let gen2 = &report.generations[1];
+    assert_eq!(gen2.stale_bytes, Some(RM_KEY1.len() as u64));
+    assert!(gen2.is_orphaned());
+
+    Ok(())
+}
+
+// Live and stale counts of encrypted records are unknown, not zero
+#[test]
+fn verify_encrypted_directory() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().encryption_key(EncryptionKey::new(1, [7; 32]));
+    let mut store = options.open(temp_dir.path())?;
+    for key_id in 0..10 {
+        store.set(format!("key{}", key_id), "value".to_owned())?;
+    }
+    drop(store);
+
+    let report = KvStore::verify(temp_dir.path())?;
+    assert!(report.is_clean());
+    assert_eq!(report.live_keys, None);
+    let gen1 = &report.generations[0];
+    assert_eq!(gen1.records, 10);
+    assert_eq!(gen1.sealed_records, 10);
+    assert_eq!(gen1.live_keys, None);
+    assert_eq!(gen1.stale_ratio(), None);
+    assert!(!gen1.is_orphaned());
+
+    Ok(())
+}
+
+// Corrupt bytes should be reported and dropped by repair
+#[test]
+fn verify_and_repair_corruption() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let garbage = "{\"Set\":{\"ke";
+    fs::write(
+        temp_dir.path().join("1.log"),
+        [SET_KEY1, garbage, SET_KEY2].concat(),
+    )?;
+    assert!(KvStore::open(temp_dir.path()).is_err());
+
+    let report = KvStore::verify(temp_dir.path())?;
+    assert!(!report.is_clean());
+    let start = SET_KEY1.len() as u64;
+    assert_eq!(
+        report.generations[0].corrupt,
+        vec![start..start + garbage.len() as u64]
+    );
+
+    KvStore::repair(temp_dir.path())?;
+    assert!(KvStore::verify(temp_dir.path())?.is_clean());
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+#[test]
+fn dump_records() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(
+        temp_dir.path().join("1.log"),
+        [SET_KEY1, "garbage"].concat(),
+    )?;
+    fs::write(temp_dir.path().join("2.log"), [SET_KEY2, RM_KEY1].concat())?;
+
+    let dump = |gen: Option<u64>, key: Option<&str>| -> Result<Vec<LogEntry>> {
+        let mut entries = Vec::new();
+        KvStore::dump(temp_dir.path(), gen, key, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+        Ok(entries)
+    };
+
+    let entries = dump(None, None)?;
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.gen, entry.offset, entry.len, entry.key()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (1, 0, SET_KEY1.len() as u64, Some("key1")),
+            (1, SET_KEY1.len() as u64, 7, None),
+            (2, 0, SET_KEY2.len() as u64, Some("key2")),
+            (2, SET_KEY2.len() as u64, RM_KEY1.len() as u64, Some("key1")),
+        ]
+    );
+    assert_eq!(entries[1].kind, EntryKind::Corrupt);
+    assert_eq!(
+        entries[3].kind,
+        EntryKind::Rm {
+            key: "key1".to_owned()
+        }
+    );
+
+    let entries = dump(Some(2), None)?;
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|entry| entry.gen == 2));
+
+    let entries = dump(None, Some("key1"))?;
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|entry| entry.key() == Some("key1")));
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use kvs::{EncryptionKey, Error, KvStore, KvsEngine, Result};
+use tempfile::TempDir;
+
+// Should move only live pairs from one store to another
+#[test]
+fn export_import_live_pairs() -> Result<()> {
+    let source_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut source = KvStore::open(source_dir.path())?;
+    source.set("key1".to_owned(), "value1".to_owned())?;
+    source.set("key2".to_owned(), "value2".to_owned())?;
+    source.set("key1".to_owned(), "value3".to_owned())?;
+    source.remove("key2".to_owned())?;
+
+    let mut dump = Vec::new();
+    assert_eq!(kvs::export(&mut source, &mut dump)?, 1);
+    assert_eq!(
+        String::from_utf8(dump.clone()).unwrap(),
+        "{\"key\":\"key1\",\"value\":\"value3\"}\n"
+    );
+
+    let target_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut target = KvStore::open(target_dir.path())?;
+    assert_eq!(kvs::import(&mut target, dump.as_slice())?, 1);
+    assert_eq!(target.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(target.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+#[test]
+fn import_invalid_input() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(kvs::import(&mut store, "{\"key\":\"key1\"}\n".as_bytes()).is_err());
+    Ok(())
+}
+
+// Encrypted exports should need the key to import, and keep their line order
+#[test]
+fn export_import_encrypted() -> Result<()> {
+    let keys = [EncryptionKey::new(1, [7; 32])];
+    let source_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut source = KvStore::open(source_dir.path())?;
+    source.set("key1".to_owned(), "value1".to_owned())?;
+    source.set("key2".to_owned(), "value2".to_owned())?;
+
+    let mut dump = Vec::new();
+    assert_eq!(kvs::export_encrypted(&mut source, &mut dump, &keys[0])?, 2);
+    let text = String::from_utf8(dump.clone()).unwrap();
+    assert!(!text.contains("value1") && !text.contains("key2"));
+
+    let target_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut target = KvStore::open(target_dir.path())?;
+    match kvs::import(&mut target, dump.as_slice()) {
+        Err(Error::MissingKey(1)) => {}
+        res => panic!("Expected a missing key, got {:?}", res),
+    }
+    let lines: Vec<_> = text.lines().collect();
+    let swapped = format!("{}\n{}\n", lines[1], lines[0]);
+    match kvs::import_encrypted(&mut target, swapped.as_bytes(), &keys) {
+        Err(Error::Decryption) => {}
+        res => panic!("Expected a decryption failure, got {:?}", res),
+    }
+
+    assert_eq!(
+        kvs::import_encrypted(&mut target, dump.as_slice(), &keys)?,
+        2
+    );
+    assert_eq!(target.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(target.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
@@ -1,4 +1,8 @@
-use kvs::{KvStore, KvsEngine, Result};
+use kvs::{
+    Compression, EncryptionKey, Error, GenerationStats, IndexMode, KvStore, KvStoreOptions,
+    KvsEngine, Result, WatchEvent,
+};
+use std::fs;
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -124,3 +128,738 @@ fn compaction() -> Result<()> {
 
     panic!("No compaction detected");
 }
+
+// Should report live keys, stale bytes and compactions
+#[test]
+fn stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+
+    let stats = store.stats()?;
+    assert_eq!(stats.live_keys, 2);
+    assert_eq!(stats.compactions, 0);
+    assert_eq!(stats.last_compaction, None);
+    let record_len = r#"{"Set":{"key":"key1","value":"value1"}}"#.len() as u64;
+    assert_eq!(
+        stats.generations,
+        vec![GenerationStats {
+            gen: 1,
+            total_bytes: 3 * record_len,
+            stale_bytes: record_len,
+        }]
+    );
+
+    let reader = KvStore::open_read_only(temp_dir.path())?;
+    for iter in 0..1000 {
+        for key_id in 0..100 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+        if store.stats()?.compactions > 0 {
+            let stats = store.stats()?;
+            assert_eq!(stats.live_keys, 100);
+            assert!(stats.last_compaction.is_some());
+
+            // A reader opened before still reports the generations it sees
+            let stale = reader.stats()?;
+            assert_eq!(stale.live_keys, 2);
+            assert_eq!(stale.generations.len(), 1);
+
+            // The compaction history is kept in the data directory
+            drop(store);
+            let reopened = KvStore::open_read_only(temp_dir.path())?.stats()?;
+            assert_eq!(reopened.compactions, stats.compactions);
+            assert_eq!(reopened.last_compaction, stats.last_compaction);
+            return Ok(());
+        }
+    }
+
+    panic!("No compaction detected");
+}
+
+// Should refuse to open a directory which is already open
+#[test]
+fn directory_lock() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    match KvStore::open(temp_dir.path()) {
+        Err(Error::DirectoryLocked(path)) => assert_eq!(path, temp_dir.path()),
+        other => panic!("expected DirectoryLocked, got {:?}", other),
+    }
+    assert!(KvStore::repair(temp_dir.path()).is_err());
+
+    // The lock is released with the store
+    drop(store);
+    KvStore::open(temp_dir.path())?;
+
+    Ok(())
+}
+
+// Should read alongside a writer without creating files
+#[test]
+fn open_read_only() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let log_files = || {
+        WalkDir::new(temp_dir.path())
+            .into_iter()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("log".as_ref()))
+            .count()
+    };
+    let files_before = log_files();
+
+    let mut reader = KvStore::open_read_only(temp_dir.path())?;
+    assert_eq!(log_files(), files_before);
+    assert_eq!(reader.get("key1".to_owned())?, Some("value1".to_owned()));
+    match reader.set("key2".to_owned(), "value2".to_owned()) {
+        Err(Error::ReadOnly) => {}
+        other => panic!("expected ReadOnly, got {:?}", other),
+    }
+    assert!(reader.remove("key1".to_owned()).is_err());
+
+    // Records written after opening are not visible
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(reader.get("key2".to_owned())?, None);
+    let mut reader = KvStore::open_read_only(temp_dir.path())?;
+    assert_eq!(reader.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Compaction should leave a hint file next to the compacted generation
+#[test]
+fn compaction_hint_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    let files_with_extension = |extension: &str| -> Vec<_> {
+        let mut files: Vec<_> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some(extension.as_ref()))
+            .collect();
+        files.sort();
+        files
+    };
+
+    for iter in 0..1000 {
+        for key_id in 0..1000 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+        if store.stats()?.compactions == 0 {
+            continue;
+        }
+
+        let hints = files_with_extension("hint");
+        assert_eq!(hints.len(), 1);
+        let compacted_log = hints[0].with_extension("log");
+        assert!(files_with_extension("log").contains(&compacted_log));
+
+        drop(store);
+        let mut store = KvStore::open(temp_dir.path())?;
+        for key_id in 0..1000 {
+            assert_eq!(
+                store.get(format!("key{}", key_id))?,
+                Some(format!("{}", iter))
+            );
+        }
+
+        // An unreadable hint file falls back to replaying the log
+        drop(store);
+        fs::write(&hints[0], "garbage")?;
+        let mut store = KvStore::open(temp_dir.path())?;
+        assert_eq!(store.get("key0".to_owned())?, Some(format!("{}", iter)));
+        return Ok(());
+    }
+
+    panic!("No compaction detected");
+}
+
+// Should give the same results with the index kept on disk
+#[test]
+fn index_on_disk() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().index_mode(IndexMode::OnDisk { cache_blocks: 4 });
+    let mut store = options.open(temp_dir.path())?;
+
+    // Enough changes to be merged into the index file
+    for key_id in 0..20_000 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+    for key_id in (0..20_000).step_by(2) {
+        store.remove(format!("key{}", key_id))?;
+    }
+    store.set("key0".to_owned(), "new".to_owned())?;
+    assert!(temp_dir.path().join("kvs.index").is_file());
+    assert_eq!(store.stats()?.live_keys, 10_001);
+
+    let check = |store: &mut KvStore| -> Result<()> {
+        assert_eq!(store.get("key0".to_owned())?, Some("new".to_owned()));
+        assert_eq!(store.get("key2".to_owned())?, None);
+        assert_eq!(
+            store.get("key19999".to_owned())?,
+            Some("value19999".to_owned())
+        );
+        assert_eq!(store.get("key".to_owned())?, None);
+        assert_eq!(store.keys()?.len(), 10_001);
+        Ok(())
+    };
+    check(&mut store)?;
+
+    // Open from disk again, from the index file and the records after it
+    drop(store);
+    check(&mut options.open(temp_dir.path())?)?;
+    check(&mut options.clone().read_only(true).open(temp_dir.path())?)?;
+
+    // A small run of removals should shadow the older runs
+    let mut store = options.open(temp_dir.path())?;
+    store.remove("key1".to_owned())?;
+    store.set("key1".to_owned(), "again".to_owned())?;
+    store.remove("key3".to_owned())?;
+    drop(store);
+    let mut store = options.open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("again".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+    assert_eq!(store.keys()?.len(), 10_000);
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    // The memory index does not maintain the index file
+    check(&mut KvStore::open(temp_dir.path())?)?;
+    assert!(!temp_dir.path().join("kvs.index").exists());
+    check(&mut options.open(temp_dir.path())?)?;
+
+    Ok(())
+}
+
+// A read-only store should keep scanning the index it was opened with
+#[test]
+fn index_on_disk_read_only_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().index_mode(IndexMode::OnDisk { cache_blocks: 4 });
+    let mut store = options.open(temp_dir.path())?;
+    for key_id in 0..20_000 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+
+    let mut reader = options.clone().read_only(true).open(temp_dir.path())?;
+    // Enough new keys for the writer to merge and replace the runs
+    for key_id in 20_000..60_000 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+
+    let keys = reader.keys()?;
+    assert_eq!(keys.len(), 20_000);
+    assert_eq!(reader.stats()?.live_keys, 20_000);
+    assert_eq!(reader.get("key20000".to_owned())?, None);
+    assert_eq!(reader.get("key0".to_owned())?, Some("value0".to_owned()));
+
+    Ok(())
+}
+
+// Compaction should rewrite the index file
+#[test]
+fn index_on_disk_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().index_mode(IndexMode::OnDisk { cache_blocks: 4 });
+    let mut store = options.open(temp_dir.path())?;
+
+    for iter in 0..1000 {
+        for key_id in 0..1000 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+        if store.stats()?.compactions == 0 {
+            continue;
+        }
+
+        drop(store);
+        let mut store = options.open(temp_dir.path())?;
+        for key_id in 0..1000 {
+            assert_eq!(
+                store.get(format!("key{}", key_id))?,
+                Some(format!("{}", iter))
+            );
+        }
+        return Ok(());
+    }
+
+    panic!("No compaction detected");
+}
+
+// Values written with different codecs should read back and survive compaction
+#[test]
+fn compressed_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let document = |id: u32| {
+        format!(
+            "{{\"id\":{},\"body\":\"{}\"}}",
+            id,
+            "lorem ipsum ".repeat(100)
+        )
+    };
+    let log_size = |dir: &std::path::Path| -> u64 {
+        WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+            .map(|entry| entry.metadata().unwrap().len())
+            .sum()
+    };
+
+    let codecs = [Compression::Zstd, Compression::Lz4, Compression::None];
+    for (i, &compression) in codecs.iter().enumerate() {
+        let mut store = KvStoreOptions::new()
+            .compression(compression)
+            .open(temp_dir.path())?;
+        for key_id in 0..100 {
+            store.set(format!("key{}-{}", i, key_id), document(key_id))?;
+        }
+        // A short value is stored as is
+        store.set(format!("short{}", i), "x".to_owned())?;
+        if compression == Compression::Zstd {
+            assert!(log_size(temp_dir.path()) < 100 * document(0).len() as u64 / 5);
+        }
+    }
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    let check = |store: &mut KvStore| -> Result<()> {
+        for i in 0..codecs.len() {
+            for key_id in 0..100 {
+                assert_eq!(
+                    store.get(format!("key{}-{}", i, key_id))?,
+                    Some(document(key_id))
+                );
+            }
+            assert_eq!(store.get(format!("short{}", i))?, Some("x".to_owned()));
+        }
+        Ok(())
+    };
+    check(&mut store)?;
+
+    // Overwrite other keys until the mixed generations are compacted
+    let mut iter = 0;
+    while store.stats()?.compactions == 0 {
+        store.set(format!("filler{}", iter % 10), document(iter))?;
+        iter += 1;
+    }
+    check(&mut store)?;
+    drop(store);
+    check(&mut KvStore::open(temp_dir.path())?)?;
+
+    Ok(())
+}
+
+// Records and hint files should not hold plain keys or values, and compaction
+// should move them to a new key
+#[test]
+fn encryption_at_rest() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let old_key = EncryptionKey::new(1, [7; 32]);
+    let new_key = EncryptionKey::new(2, [9; 32]);
+    let contents = |dir: &std::path::Path| -> Vec<u8> {
+        WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .flat_map(|entry| fs::read(entry.path()).unwrap())
+            .collect()
+    };
+    let contains = |haystack: &[u8], needle: &str| {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    };
+
+    let mut store = KvStoreOptions::new()
+        .encryption_key(old_key.clone())
+        .open(temp_dir.path())?;
+    store.set("secret-key".to_owned(), "secret-value".to_owned())?;
+    store.set("removed-key".to_owned(), "value".to_owned())?;
+    store.remove("removed-key".to_owned())?;
+    store.compact()?;
+    store.set("other-key".to_owned(), "other-value".to_owned())?;
+    drop(store);
+
+    let files = contents(temp_dir.path());
+    for plain in &["secret-key", "secret-value", "removed-key", "other-key"] {
+        assert!(
+            !contains(&files, plain),
+            "{} is stored in plain text",
+            plain
+        );
+    }
+    assert!(temp_dir.path().read_dir()?.any(|entry| entry
+        .unwrap()
+        .path()
+        .extension()
+        .is_some_and(|ext| ext == "hint")));
+
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(Error::MissingKey(1))
+    ));
+    assert!(matches!(
+        KvStoreOptions::new()
+            .encryption_key(old_key.clone())
+            .index_mode(IndexMode::OnDisk { cache_blocks: 4 })
+            .open(temp_dir.path()),
+        Err(Error::EncryptedDiskIndex)
+    ));
+
+    // Rotate to the new key
+    let mut store = KvStoreOptions::new()
+        .encryption_key(new_key.clone())
+        .previous_key(old_key)
+        .open(temp_dir.path())?;
+    assert_eq!(
+        store.get("secret-key".to_owned())?,
+        Some("secret-value".to_owned())
+    );
+    store.compact()?;
+    drop(store);
+
+    let mut store = KvStoreOptions::new()
+        .encryption_key(new_key)
+        .open(temp_dir.path())?;
+    assert_eq!(
+        store.get("secret-key".to_owned())?,
+        Some("secret-value".to_owned())
+    );
+    assert_eq!(
+        store.get("other-key".to_owned())?,
+        Some("other-value".to_owned())
+    );
+    assert_eq!(store.get("removed-key".to_owned())?, None);
+
+    Ok(())
+}
+
+// A sealed record copied into another generation should not be accepted
+#[test]
+fn encrypted_record_replay() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().encryption_key(EncryptionKey::new(1, [7; 32]));
+
+    let mut store = options.open(temp_dir.path())?;
+    store.set("key1".to_owned(), "old".to_owned())?;
+    drop(store);
+    let mut store = options.open(temp_dir.path())?;
+    store.set("key1".to_owned(), "new".to_owned())?;
+    drop(store);
+
+    let old_record = fs::read(temp_dir.path().join("1.log"))?;
+    let mut log = fs::read(temp_dir.path().join("2.log"))?;
+    log.extend_from_slice(&old_record);
+    fs::write(temp_dir.path().join("2.log"), log)?;
+    assert!(matches!(
+        options.open(temp_dir.path()),
+        Err(Error::Decryption)
+    ));
+
+    Ok(())
+}
+
+// Repeated reads should be served by the value cache, which follows writes
+#[test]
+fn value_cache() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStoreOptions::new()
+        .value_cache(100)
+        .open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    for _ in 0..3 {
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    }
+    let stats = store.stats()?;
+    assert_eq!((stats.cache_hits, stats.cache_misses), (2, 1));
+
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+
+    // Values which do not fit are never cached, others evict older ones
+    store.set("big".to_owned(), "x".repeat(200))?;
+    store.get("big".to_owned())?;
+    store.get("big".to_owned())?;
+    for key_id in 0..10 {
+        store.set(format!("key{}", key_id), "v".repeat(20))?;
+        store.get(format!("key{}", key_id))?;
+    }
+    let before = store.stats()?;
+    store.get("key9".to_owned())?;
+    store.get("key0".to_owned())?;
+    let after = store.stats()?;
+    assert_eq!(after.cache_hits - before.cache_hits, 1);
+    assert_eq!(after.cache_misses - before.cache_misses, 1);
+
+    // Without a cache nothing is counted
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.get("key9".to_owned())?;
+    let stats = store.stats()?;
+    assert_eq!((stats.cache_hits, stats.cache_misses), (0, 0));
+
+    Ok(())
+}
+
+// Reads from mapped generations should see the same data, also across compactions
+#[test]
+fn mmap_reads() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().mmap(true);
+
+    let mut store = options.open(temp_dir.path())?;
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+    drop(store);
+
+    let mut store = options.open(temp_dir.path())?;
+    store.set("key0".to_owned(), "new".to_owned())?;
+    assert_eq!(store.get("key0".to_owned())?, Some("new".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(
+        options
+            .clone()
+            .read_only(true)
+            .open(temp_dir.path())?
+            .get("key2".to_owned())?,
+        Some("value2".to_owned())
+    );
+
+    store.compact()?;
+    store.set("key3".to_owned(), "new".to_owned())?;
+    for key_id in 0..100 {
+        let expected = match key_id {
+            0 | 3 => "new".to_owned(),
+            _ => format!("value{}", key_id),
+        };
+        assert_eq!(store.get(format!("key{}", key_id))?, Some(expected));
+    }
+
+    Ok(())
+}
+
+// Large values should go to blob files, which are collected on compaction
+#[test]
+fn blob_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().blob_threshold(1000);
+    let files = |extension: &str| -> Vec<(String, u64)> {
+        let mut files: Vec<_> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+            .map(|path| {
+                let len = fs::metadata(&path).unwrap().len();
+                (path.file_name().unwrap().to_str().unwrap().to_owned(), len)
+            })
+            .collect();
+        files.sort();
+        files
+    };
+    let value = |key_id: u32, iter: u32| format!("{}:{}:{}", key_id, iter, "x".repeat(10_000));
+
+    let mut store = options.open(temp_dir.path())?;
+    for key_id in 0..10 {
+        store.set(format!("key{}", key_id), value(key_id, 0))?;
+    }
+    store.set("small".to_owned(), "value".to_owned())?;
+    assert_eq!(files("blob").len(), 1);
+    assert!(files("log").iter().map(|(_, len)| len).sum::<u64>() < 10_000);
+    drop(store);
+
+    // Most values of the blob file are overwritten, so compaction moves the
+    // rest of them to a new one
+    let mut store = options.open(temp_dir.path())?;
+    for _ in 0..2 {
+        for key_id in 0..7 {
+            store.set(format!("key{}", key_id), value(key_id, 1))?;
+        }
+    }
+    assert_eq!(files("blob").len(), 1);
+    store.remove("key0".to_owned())?;
+    let garbage = store.stats()?.blob_garbage;
+    assert!(garbage > 0);
+    drop(store);
+
+    // The garbage count survives reopening, so overwrites made by separate
+    // processes still add up to a collection
+    let mut store = options.open(temp_dir.path())?;
+    assert_eq!(store.stats()?.blob_garbage, garbage);
+    store.compact()?;
+    assert_eq!(store.stats()?.blob_garbage, 0);
+    let blob_files = files("blob");
+    assert_eq!(blob_files.len(), 1);
+    assert!(blob_files[0].0 != "1.blob");
+
+    let check = |store: &mut KvStore| -> Result<()> {
+        assert_eq!(store.get("key0".to_owned())?, None);
+        for key_id in 1..10 {
+            let iter = if key_id < 7 { 1 } else { 0 };
+            assert_eq!(
+                store.get(format!("key{}", key_id))?,
+                Some(value(key_id, iter))
+            );
+        }
+        assert_eq!(store.get("small".to_owned())?, Some("value".to_owned()));
+        Ok(())
+    };
+    check(&mut store)?;
+    drop(store);
+    check(&mut options.open(temp_dir.path())?)?;
+    check(&mut KvStore::open(temp_dir.path())?)?;
+
+    Ok(())
+}
+
+// Processes writing a few large values each should share a blob file
+#[test]
+fn blob_files_shared() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions::new().blob_threshold(1000);
+    let blob_files = || {
+        fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let path = entry.as_ref().unwrap().path();
+                path.extension().is_some_and(|ext| ext == "blob")
+            })
+            .count()
+    };
+
+    for key_id in 0..20 {
+        let mut store = options.open(temp_dir.path())?;
+        store.set(format!("key{}", key_id), "x".repeat(10_000))?;
+    }
+    assert_eq!(blob_files(), 1);
+
+    let mut store = options.open(temp_dir.path())?;
+    store.compact()?;
+    assert_eq!(blob_files(), 1);
+    for key_id in 0..20 {
+        assert_eq!(
+            store.get(format!("key{}", key_id))?,
+            Some("x".repeat(10_000))
+        );
+    }
+
+    Ok(())
+}
+
+// Changes should be read back in log order, in batches, and resumable across
+// compactions which the consumer had caught up with
+#[test]
+fn change_stream() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let set = |key: &str, value: &str| WatchEvent::Set {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    };
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key1".to_owned())?;
+    let changes = store.changes(0, usize::MAX)?;
+    let events: Vec<_> = changes.iter().map(|change| change.event.clone()).collect();
+    assert_eq!(
+        events,
+        vec![
+            set("key1", "value1"),
+            set("key2", "value2"),
+            WatchEvent::Removed {
+                key: "key1".to_owned()
+            },
+        ]
+    );
+    assert!(changes.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+
+    let batch = store.changes(0, 2)?;
+    assert_eq!(batch, changes[..2]);
+    assert_eq!(store.changes(batch[1].seq, 2)?, changes[2..]);
+
+    let resume = changes.last().unwrap().seq;
+    assert!(store.changes(resume, usize::MAX)?.is_empty());
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    let changes = store.changes(resume, usize::MAX)?;
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].event, set("key3", "value3"));
+    let tip = changes[0].seq;
+
+    // Consumers behind the compaction read on in the replaced generations,
+    // one at the tip carries on with the writes made after it
+    store.compact()?;
+    let changes = store.changes(resume, usize::MAX)?;
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].event, set("key3", "value3"));
+    assert!(store.changes(tip, usize::MAX)?.is_empty());
+    let mut events: Vec<_> = store
+        .changes(0, usize::MAX)?
+        .into_iter()
+        .map(|change| change.event)
+        .collect();
+    events.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+    assert_eq!(events, vec![set("key2", "value2"), set("key3", "value3")]);
+
+    store.set("key4".to_owned(), "value4".to_owned())?;
+    let changes = store.changes(tip, usize::MAX)?;
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].event, set("key4", "value4"));
+    let tip = changes[0].seq;
+
+    store.compact()?;
+    store.compact()?;
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(store.changes(tip, usize::MAX)?.is_empty());
+
+    // Generations before the latest compaction are only kept up to the
+    // retention, none by default
+    match store.changes(resume, usize::MAX) {
+        Err(Error::Compacted(seq)) => assert_eq!(seq, resume),
+        res => panic!("Expected a compacted position, got {:?}", res),
+    }
+    drop(store);
+
+    let mut store = KvStoreOptions::new()
+        .change_retention(u64::MAX)
+        .open(temp_dir.path())?;
+    store.set("key5".to_owned(), "value5".to_owned())?;
+    store.compact()?;
+    store.compact()?;
+    let changes = store.changes(tip, usize::MAX)?;
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].event, set("key5", "value5"));
+
+    Ok(())
+}
+
+// Sequence numbers should not wrap around once generations outgrow them
+#[test]
+fn change_stream_overflow() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(
+        temp_dir.path().join(format!("{}.log", 1u64 << 24)),
+        r#"{"Set":{"key":"key1","value":"value1"}}"#,
+    )?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    match store.changes(0, usize::MAX) {
+        Err(Error::SequenceOverflow(gen, _)) => assert_eq!(gen, 1 << 24),
+        res => panic!("Expected a sequence overflow, got {:?}", res),
+    }
+
+    Ok(())
+}
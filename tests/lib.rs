@@ -1,2 +1,7 @@
+mod admin;
 mod cli;
+mod jsonl;
 mod kv_store;
+mod lsm;
+mod memory;
+mod watch;
@@ -0,0 +1,96 @@
+use kvs::{Error, KvsEngine, LsmStore, Result};
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+// Should get previously stored values, also after reopening
+#[test]
+fn lsm_get_stored_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+
+    drop(store);
+    let mut store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should remove keys and refuse to remove missing ones
+#[test]
+fn lsm_remove_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(matches!(
+        store.remove("key1".to_owned()),
+        Err(Error::KeyNotFound(_))
+    ));
+
+    drop(store);
+    let mut store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(store.keys()?.is_empty());
+
+    Ok(())
+}
+
+// Should flush to tables and compact them without losing or resurrecting keys
+#[test]
+fn lsm_flush_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open(temp_dir.path())?;
+
+    let value = "v".repeat(1000);
+    for iter in 0..8 {
+        for key_id in 0..1000 {
+            let key = format!("key{:04}", key_id);
+            store.set(key, format!("{}{}", value, iter))?;
+        }
+    }
+    for key_id in (0..1000).step_by(2) {
+        store.remove(format!("key{:04}", key_id))?;
+    }
+
+    let tables = WalkDir::new(temp_dir.path())
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "sst"))
+        .count();
+    assert!(tables > 0);
+    assert!(
+        tables < 8,
+        "level 0 tables should have been compacted, found {}",
+        tables
+    );
+
+    for reopen in 0..2 {
+        if reopen == 1 {
+            drop(store);
+            store = LsmStore::open(temp_dir.path())?;
+        }
+        for key_id in 0..1000 {
+            let expected = if key_id % 2 == 0 {
+                None
+            } else {
+                Some(format!("{}7", value))
+            };
+            assert_eq!(store.get(format!("key{:04}", key_id))?, expected);
+        }
+        let keys = store.keys()?;
+        assert_eq!(keys.len(), 500);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use kvs::{Error, KvsEngine, MemoryEngine, Result};
+use tempfile::TempDir;
+
+// Should get, overwrite and remove values without touching the filesystem
+#[test]
+fn memory_get_set_remove() -> Result<()> {
+    let mut store = MemoryEngine::new();
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(matches!(
+        store.remove("key1".to_owned()),
+        Err(Error::KeyNotFound(_))
+    ));
+
+    Ok(())
+}
+
+// Should restore keys from the snapshot written on drop
+#[test]
+fn memory_snapshot() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let snapshot = temp_dir.path().join("kvs.snapshot");
+
+    let mut store = MemoryEngine::with_snapshot(&snapshot)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.snapshot()?;
+    store.remove("key2".to_owned())?;
+    drop(store);
+
+    let mut store = MemoryEngine::with_snapshot(&snapshot)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.keys()?, vec!["key1".to_owned()]);
+
+    Ok(())
+}
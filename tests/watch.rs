@@ -0,0 +1,44 @@
+use kvs::{KvStore, KvsEngine, LsmStore, MemoryEngine, Result, WatchEvent};
+use tempfile::TempDir;
+
+fn check_watch(engine: &mut dyn KvsEngine) -> Result<()> {
+    let users = engine.watch("user/");
+    let all = engine.watch("");
+
+    engine.set("user/1".to_owned(), "alice".to_owned())?;
+    engine.set("group/1".to_owned(), "admins".to_owned())?;
+    engine.remove("user/1".to_owned())?;
+    assert!(engine.remove("user/2".to_owned()).is_err());
+
+    let events: Vec<_> = users.try_iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            WatchEvent::Set {
+                key: "user/1".to_owned(),
+                value: "alice".to_owned(),
+            },
+            WatchEvent::Removed {
+                key: "user/1".to_owned(),
+            },
+        ]
+    );
+    assert_eq!(all.try_iter().count(), 3);
+
+    // Dropped receivers must not make later writes fail.
+    drop(users);
+    engine.set("user/3".to_owned(), "carol".to_owned())?;
+    assert_eq!(all.try_iter().count(), 1);
+
+    Ok(())
+}
+
+// Watchers should only see successful changes to keys under their prefix
+#[test]
+fn watch_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_watch(&mut KvStore::open(temp_dir.path().join("kvs"))?)?;
+    check_watch(&mut LsmStore::open(temp_dir.path().join("lsm"))?)?;
+    check_watch(&mut MemoryEngine::new())?;
+    Ok(())
+}